@@ -78,5 +78,15 @@ extern crate stats;
 #[cfg(feature = "ipc")]
 extern crate ethcore_ipc as ipc;
 
+#[cfg(feature = "serialize")]
+extern crate serde;
+
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(all(test, feature = "serialize"))]
+extern crate serde_json;
+
 #[cfg(test)]
 extern crate ethcore_devtools as devtools;
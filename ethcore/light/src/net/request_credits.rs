@@ -104,6 +104,27 @@ impl Default for CostTable {
 	}
 }
 
+impl CostTable {
+	/// Get the cost per packet.
+	pub fn base_cost(&self) -> U256 { self.base }
+
+	/// Compute the cost of a single request, not including the base cost.
+	/// Costs depend only on scalar parameters of requests, so this is exact
+	/// even for requests with unfilled back-references.
+	pub fn compute_cost(&self, request: &Request) -> U256 {
+		match *request {
+			Request::Headers(ref req) => self.headers * req.max.into(),
+			Request::HeaderProof(_) => self.header_proof,
+			Request::Body(_) => self.body,
+			Request::Receipts(_) => self.receipts,
+			Request::Account(_) => self.account,
+			Request::Storage(_) => self.storage,
+			Request::Code(_) => self.code,
+			Request::Execution(ref req) => self.transaction_proof * req.gas,
+		}
+	}
+}
+
 impl Encodable for CostTable {
 	fn rlp_append(&self, s: &mut RlpStream) {
 		fn append_cost(s: &mut RlpStream, cost: &U256, kind: request::Kind) {
@@ -223,16 +244,7 @@ impl FlowParams {
 	/// Compute the actual cost of a request, given the kind of request
 	/// and number of requests made.
 	pub fn compute_cost(&self, request: &Request) -> U256 {
-		match *request {
-			Request::Headers(ref req) => self.costs.headers * req.max.into(),
-			Request::HeaderProof(_) => self.costs.header_proof,
-			Request::Body(_) => self.costs.body,
-			Request::Receipts(_) => self.costs.receipts,
-			Request::Account(_) => self.costs.account,
-			Request::Storage(_) => self.costs.storage,
-			Request::Code(_) => self.costs.code,
-			Request::Execution(ref req) => self.costs.transaction_proof * req.gas,
-		}
+		self.costs.compute_cost(request)
 	}
 
 	/// Compute the cost of a set of requests.
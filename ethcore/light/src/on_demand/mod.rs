@@ -320,7 +320,7 @@ impl OnDemand {
 	/// Fails if back-references are not coherent.
 	/// The returned vector of responses will correspond to the requests exactly.
 	pub fn request_raw(&self, ctx: &BasicContext, requests: Vec<Request>)
-		-> Result<Receiver<Vec<Response>>, basic_request::NoSuchOutput>
+		-> Result<Receiver<Vec<Response>>, basic_request::InvalidBackReference>
 	{
 		let (sender, receiver) = oneshot::channel();
 
@@ -356,7 +356,7 @@ impl OnDemand {
 	/// Submit a strongly-typed batch of requests.
 	///
 	/// Fails if back-reference are not coherent.
-	pub fn request<T>(&self, ctx: &BasicContext, requests: T) -> Result<OnResponses<T>, basic_request::NoSuchOutput>
+	pub fn request<T>(&self, ctx: &BasicContext, requests: T) -> Result<OnResponses<T>, basic_request::InvalidBackReference>
 		where T: request::RequestAdapter
 	{
 		self.request_raw(ctx, requests.make_requests()).map(|recv| OnResponses {
@@ -486,7 +486,7 @@ impl Handler for OnDemand {
 				}
 				Err(e) => {
 					let peer = ctx.peer();
-					debug!(target: "on_demand", "Peer {} gave bad response: {:?}", peer, e);
+					debug!(target: "on_demand", "Peer {} gave bad response to {}", peer, e);
 					ctx.disable_peer(peer);
 
 					break;
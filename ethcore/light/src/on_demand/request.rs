@@ -325,6 +325,14 @@ impl IncompleteRequest for CheckedRequest {
 	fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
 		match_me!(*self, (_, ref mut req) => req.adjust_refs(mapping))
 	}
+
+	fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+		match_me!(*self, (_, ref req) => req.back_references())
+	}
+
+	fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+		match_me!(*self, (_, ref mut req) => req.restore_back_references(refs))
+	}
 }
 
 impl net_request::CheckedRequest for CheckedRequest {
@@ -18,90 +18,795 @@
 //! Push requests with `push`. Back-references and data required to verify responses must be
 //! supplied as well.
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::iter::FromIterator;
+use std::time::Instant;
+use std::usize;
+use net::request_credits::CostTable;
+use rlp::{self, Encodable};
 use request::{
-	IncompleteRequest, OutputKind, Output, NoSuchOutput, ResponseError, ResponseLike,
+	IncompleteRequest, OutputKind, Output, NoSuchOutput, InvalidBackReference, ResponseError, IndexedResponseError,
+	ResponseLike, WrongKind,
 };
+use util::U256;
 
 /// Build chained requests. Push them onto the series with `push`,
 /// and produce a `Requests` object with `build`. Outputs are checked for consistency.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Skip conditions are function pointers, which can't be compared reliably, so
+/// equality only considers which requests are optional.
+#[derive(Debug, Clone)]
 pub struct RequestBuilder<T> {
 	output_kinds: HashMap<(usize, usize), OutputKind>,
 	requests: Vec<T>,
+	conditions: HashMap<usize, SkipPredicate>, // skip conditions of optional requests.
+}
+
+impl<T: PartialEq> PartialEq for RequestBuilder<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.output_kinds == other.output_kinds
+			&& self.requests == other.requests
+			&& same_optional(&self.conditions, &other.conditions)
+	}
 }
 
+impl<T: Eq> Eq for RequestBuilder<T> {}
+
 impl<T> Default for RequestBuilder<T> {
 	fn default() -> Self {
 		RequestBuilder {
 			output_kinds: HashMap::new(),
 			requests: Vec::new(),
+			conditions: HashMap::new(),
+		}
+	}
+}
+
+/// Condition under which an optional request is skipped, evaluated against
+/// the outputs collected by the time the request is reached.
+pub type SkipPredicate = fn(&HashMap<(usize, usize), Output>) -> bool;
+
+// whether both sets of skip conditions belong to the same requests.
+fn same_optional(a: &HashMap<usize, SkipPredicate>, b: &HashMap<usize, SkipPredicate>) -> bool {
+	a.len() == b.len() && a.keys().all(|idx| b.contains_key(idx))
+}
+
+/// A request which is only sent if its skip condition doesn't hold.
+#[derive(Debug, Clone)]
+pub struct Optional<T> {
+	/// The request.
+	pub request: T,
+	/// Condition under which the request is skipped.
+	pub skip_if: SkipPredicate,
+}
+
+impl<T> Optional<T> {
+	/// Create an optional request, skipped whenever `skip_if` holds.
+	pub fn new(request: T, skip_if: SkipPredicate) -> Self {
+		Optional {
+			request: request,
+			skip_if: skip_if,
+		}
+	}
+}
+
+impl<T> RequestBuilder<T> {
+	/// Create a builder with space preallocated for `n` requests.
+	pub fn with_capacity(n: usize) -> Self {
+		RequestBuilder {
+			output_kinds: HashMap::with_capacity(n),
+			requests: Vec::with_capacity(n),
+			conditions: HashMap::new(),
 		}
 	}
+
+	/// Get the kind of the given output of a request in the chain, if it has been noted.
+	pub fn output_kind_at(&self, req: usize, idx: usize) -> Option<OutputKind> {
+		self.output_kinds.get(&(req, idx)).cloned()
+	}
+
+	/// Whether the given output of a request in the chain has been noted with
+	/// exactly the given kind. Use `Field::expected_kind` to get the kind
+	/// consumed by a field.
+	pub fn can_reference(&self, consuming_kind: OutputKind, req: usize, idx: usize) -> bool {
+		self.output_kinds.get(&(req, idx)) == Some(&consuming_kind)
+	}
+
+	/// Get the output of the given kind noted by the latest request in the chain,
+	/// as (request index, output index). If that request notes several outputs
+	/// of the kind, the last of them is yielded.
+	pub fn last_output_of_kind(&self, kind: OutputKind) -> Option<(usize, usize)> {
+		self.output_kinds.iter()
+			.filter(|&(_, k)| k == &kind)
+			.map(|(&key, _)| key)
+			.max()
+	}
 }
 
 impl<T: IncompleteRequest> RequestBuilder<T> {
 	/// Attempt to push a request onto the request chain. Fails if the request
-	/// references a non-existent output of a prior request.
-	pub fn push(&mut self, request: T) -> Result<(), NoSuchOutput> {
-		request.check_outputs(|req, idx, kind| {
-			match self.output_kinds.get(&(req, idx)) {
-				Some(k) if k == &kind => Ok(()),
-				_ => Err(NoSuchOutput),
-			}
-		})?;
+	/// references a non-existent output of a prior request, yielding the
+	/// offending reference.
+	pub fn push(&mut self, request: T) -> Result<(), InvalidBackReference> {
+		check_back_references(&self.output_kinds, &request)?;
+
 		let req_idx = self.requests.len();
 		request.note_outputs(|idx, kind| { self.output_kinds.insert((req_idx, idx), kind); });
 		self.requests.push(request);
 		Ok(())
 	}
 
+	/// Attempt to push an optional request onto the request chain.
+	/// Once all requests before it are answered, the request is skipped if its
+	/// condition holds, as are all later requests referencing its outputs.
+	pub fn push_optional(&mut self, optional: Optional<T>) -> Result<(), InvalidBackReference> {
+		self.push(optional.request)?;
+		self.conditions.insert(self.requests.len() - 1, optional.skip_if);
+		Ok(())
+	}
+
+	/// Attempt to insert a request into the request chain at the given index,
+	/// shifting all requests after it up by one and adjusting back-references
+	/// accordingly. Fails if the request references an output of a request
+	/// at or after `at`, or a non-existent output.
+	///
+	/// Panics if `at` is greater than the number of requests.
+	pub fn insert(&mut self, at: usize, request: T) -> Result<(), InvalidBackReference> {
+		assert!(at <= self.requests.len(), "insertion index out of bounds");
+
+		check_references(&request, |req, idx, kind| req < at && self.output_kinds.get(&(req, idx)) == Some(&kind))?;
+
+		let shift = |req_idx: usize| if req_idx >= at { req_idx + 1 } else { req_idx };
+		for req in self.requests.iter_mut().skip(at) {
+			req.adjust_refs(&shift);
+		}
+
+		let output_kinds: HashMap<_, _> = self.output_kinds.drain()
+			.map(|((req, idx), kind)| ((shift(req), idx), kind))
+			.collect();
+		self.output_kinds = output_kinds;
+
+		let conditions: HashMap<_, _> = self.conditions.drain()
+			.map(|(req, skip_if)| (shift(req), skip_if))
+			.collect();
+		self.conditions = conditions;
+
+		request.note_outputs(|idx, kind| { self.output_kinds.insert((at, idx), kind); });
+		self.requests.insert(at, request);
+		Ok(())
+	}
+
+	/// Push a request onto the request chain unless an identical request is
+	/// already present. Yields the index of the request within the chain,
+	/// whose outputs should be used for any later back-references.
+	pub fn push_dedup(&mut self, request: T) -> Result<usize, InvalidBackReference> where T: PartialEq {
+		if let Some(idx) = self.requests.iter().position(|r| r == &request) {
+			return Ok(idx);
+		}
+
+		self.push(request)?;
+		Ok(self.requests.len() - 1)
+	}
+
+	/// Push a series of requests onto the request chain, in order.
+	/// On failure, yields the index of the offending request within `reqs`.
+	/// Requests pushed prior to the failure remain in the builder.
+	pub fn push_batch(&mut self, reqs: Vec<T>) -> Result<(), (usize, InvalidBackReference)> {
+		for (i, req) in reqs.into_iter().enumerate() {
+			self.push(req).map_err(|e| (i, e))?;
+		}
+
+		Ok(())
+	}
+
+	/// Push all requests yielded by the iterator onto the request chain, in order.
+	/// Stops at the first invalid back-reference; requests pushed prior to it
+	/// remain in the builder.
+	pub fn try_extend<I: IntoIterator<Item=T>>(&mut self, iter: I) -> Result<(), InvalidBackReference> {
+		for req in iter {
+			self.push(req)?;
+		}
+
+		Ok(())
+	}
+
+	/// Remove the last request from the chain, along with the outputs it noted.
+	/// Back-references only point backwards, so no earlier request is invalidated.
+	pub fn pop(&mut self) -> Option<T> {
+		let req = self.requests.pop();
+		if req.is_some() {
+			let req_idx = self.requests.len();
+			self.output_kinds.retain(|&(req, _), _| req != req_idx);
+			self.conditions.remove(&req_idx);
+		}
+
+		req
+	}
+
+	/// Get all noted outputs which no request in the chain references, in order.
+	pub fn unused_outputs(&self) -> Vec<(usize, usize)> {
+		let mut used = HashSet::new();
+		for req in &self.requests {
+			let _ = req.check_outputs(|req, idx, _| { used.insert((req, idx)); Ok(()) });
+		}
+
+		let mut unused: Vec<_> = self.output_kinds.keys()
+			.filter(|key| !used.contains(*key))
+			.cloned()
+			.collect();
+
+		unused.sort();
+		unused
+	}
+
+	/// Retain only the requests for which the predicate holds, adjusting
+	/// back-references of the remaining requests. Fails without altering the
+	/// chain if a retained request references an output of a removed one.
+	pub fn retain<F>(&mut self, mut f: F) -> Result<(), InvalidBackReference> where F: FnMut(&T) -> bool {
+		let keep: Vec<bool> = self.requests.iter().map(|req| f(req)).collect();
+
+		let mut retained = 0;
+		let new_idx: Vec<Option<usize>> = keep.iter().map(|&k| if k {
+			retained += 1;
+			Some(retained - 1)
+		} else {
+			None
+		}).collect();
+
+		for (req, _) in self.requests.iter().zip(&keep).filter(|&(_, &k)| k) {
+			check_references(req, |req, _, _| new_idx[req].is_some())?;
+		}
+
+		let requests = ::std::mem::replace(&mut self.requests, Vec::with_capacity(retained));
+		let mut conditions = ::std::mem::replace(&mut self.conditions, HashMap::new());
+		self.output_kinds.clear();
+
+		for (idx, (mut req, k)) in requests.into_iter().zip(keep).enumerate() {
+			if !k { continue }
+
+			req.adjust_refs(|idx| new_idx[idx].expect("back-references to removed requests checked above; qed"));
+			self.push(req).expect("relative order of retained requests is preserved; qed");
+			if let Some(skip_if) = conditions.remove(&idx) {
+				self.conditions.insert(self.requests.len() - 1, skip_if);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Get the outputs declared by the request at the given index,
+	/// ordered by output index.
+	pub fn outputs_of(&self, req_idx: usize) -> Vec<(usize, OutputKind)> {
+		let mut outputs: Vec<_> = self.output_kinds.iter()
+			.filter(|&(&(req, _), _)| req == req_idx)
+			.map(|(&(_, idx), kind)| (idx, kind.clone()))
+			.collect();
+
+		outputs.sort_by_key(|&(idx, _)| idx);
+		outputs
+	}
+
+	/// Get all noted outputs grouped by their kind, each group in order.
+	pub fn outputs_by_kind(&self) -> HashMap<OutputKind, Vec<(usize, usize)>> {
+		let mut by_kind = HashMap::new();
+		for (&key, kind) in &self.output_kinds {
+			by_kind.entry(kind.clone()).or_insert_with(Vec::new).push(key);
+		}
+
+		for outputs in by_kind.values_mut() {
+			outputs.sort();
+		}
+
+		by_kind
+	}
+
 	/// Get a reference to the output kinds map.
 	pub fn output_kinds(&self) -> &HashMap<(usize, usize), OutputKind> {
 		&self.output_kinds
 	}
 
 	/// Convert this into a "requests" object.
+	/// The outputs map is sized to hold every output noted by the builder.
+	/// Leading optional requests whose condition already holds are skipped.
 	pub fn build(self) -> Requests<T> {
-		Requests {
-			outputs: HashMap::new(),
+		let priorities = vec![0; self.requests.len()];
+		let mut requests = Requests {
+			outputs: HashMap::with_capacity(self.output_kinds.len()),
+			output_kinds: self.output_kinds,
+			back_refs: self.requests.iter().map(|req| req.back_references()).collect(),
 			requests: self.requests,
 			answered: 0,
+			deadlines: HashMap::new(),
+			buffered: HashMap::new(),
+			fill_trace: None,
+			priorities: priorities,
+			conditions: self.conditions,
+			skipped: HashSet::new(),
+		};
+
+		requests.skip_optional();
+		requests
+	}
+}
+
+// check that every back-reference of the request points to a noted output of the right kind.
+fn check_back_references<T: IncompleteRequest>(output_kinds: &HashMap<(usize, usize), OutputKind>, request: &T)
+	-> Result<(), InvalidBackReference>
+{
+	check_references(request, |req, idx, kind| output_kinds.get(&(req, idx)) == Some(&kind))
+}
+
+// check that every back-reference of the request is valid, yielding the first one which isn't.
+fn check_references<T, F>(request: &T, mut is_valid: F) -> Result<(), InvalidBackReference>
+	where T: IncompleteRequest, F: FnMut(usize, usize, OutputKind) -> bool
+{
+	let mut bad_ref = None;
+	let checked = request.check_outputs(|req, idx, kind| {
+		if is_valid(req, idx, kind) {
+			Ok(())
+		} else {
+			bad_ref = Some(InvalidBackReference { req: req, idx: idx });
+			Err(NoSuchOutput)
+		}
+	});
+
+	match checked {
+		Ok(()) => Ok(()),
+		Err(_) => Err(bad_ref.expect("`check_outputs` only fails through the provided checker; qed")),
+	}
+}
+
+/// Validate a chain of requests lazily, as it is produced by an iterator.
+/// Yields each request once its back-references have been checked, so that
+/// requests may be dispatched without holding the whole chain in memory.
+/// Only the kinds of noted outputs are retained.
+///
+/// Yields the offending reference and stops at the first invalid request.
+#[derive(Debug, Clone)]
+pub struct RequestStream<I> {
+	inner: I,
+	output_kinds: HashMap<(usize, usize), OutputKind>,
+	next_idx: usize,
+	failed: bool,
+}
+
+impl<I> RequestStream<I> where I: Iterator, I::Item: IncompleteRequest {
+	/// Create a stream validating the requests produced by given iterator.
+	pub fn new<T>(requests: T) -> Self where T: IntoIterator<IntoIter = I, Item = I::Item> {
+		RequestStream {
+			inner: requests.into_iter(),
+			output_kinds: HashMap::new(),
+			next_idx: 0,
+			failed: false,
+		}
+	}
+
+	/// Get the kinds of outputs noted by the requests yielded so far.
+	pub fn output_kinds(&self) -> &HashMap<(usize, usize), OutputKind> {
+		&self.output_kinds
+	}
+
+	/// Get the number of requests yielded so far.
+	pub fn num_yielded(&self) -> usize {
+		self.next_idx
+	}
+}
+
+impl<I> Iterator for RequestStream<I> where I: Iterator, I::Item: IncompleteRequest {
+	type Item = Result<I::Item, InvalidBackReference>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed { return None }
+
+		let request = match self.inner.next() {
+			Some(request) => request,
+			None => return None,
+		};
+		if let Err(e) = check_back_references(&self.output_kinds, &request) {
+			self.failed = true;
+			return Some(Err(e));
+		}
+
+		let req_idx = self.next_idx;
+		let output_kinds = &mut self.output_kinds;
+		request.note_outputs(|idx, kind| { output_kinds.insert((req_idx, idx), kind); });
+		self.next_idx += 1;
+		Some(Ok(request))
+	}
+}
+
+/// Error pushing a request onto the chain with `push_within_budget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushError {
+	/// The request references a non-existent or wrongly-typed output.
+	BadReference(InvalidBackReference),
+	/// The cost of the request exceeds the remaining budget.
+	OverBudget {
+		/// Cost of the rejected request.
+		cost: U256,
+		/// Budget remaining at the time of the push.
+		remaining: U256,
+	},
+}
+
+impl From<InvalidBackReference> for PushError {
+	fn from(err: InvalidBackReference) -> Self {
+		PushError::BadReference(err)
+	}
+}
+
+impl RequestBuilder<super::Request> {
+	/// Attempt to push a request onto the request chain, deducting its cost
+	/// under the given table from `remaining`. The base cost of the packet is not
+	/// included. Fails without altering the chain or the budget if the cost
+	/// exceeds the remaining budget or the request has an invalid back-reference.
+	pub fn push_within_budget(&mut self, request: super::Request, table: &CostTable, remaining: &mut U256)
+		-> Result<(), PushError>
+	{
+		let cost = table.compute_cost(&request);
+		if cost > *remaining {
+			return Err(PushError::OverBudget { cost: cost, remaining: *remaining });
+		}
+
+		self.push(request)?;
+		*remaining = *remaining - cost;
+		Ok(())
+	}
+
+	/// Render the request chain as a Graphviz DOT graph, with one node per request
+	/// and an edge from each consuming request to the request producing the output.
+	pub fn to_dot(&self) -> String {
+		use std::fmt::Write;
+
+		let mut dot = String::from("digraph requests {\n");
+		for (i, req) in self.requests.iter().enumerate() {
+			let _ = writeln!(dot, "\treq{} [label=\"{}: {:?}\"];", i, i, req.kind());
+		}
+
+		for (i, req) in self.requests.iter().enumerate() {
+			let _ = req.check_outputs(|req, idx, kind| {
+				let _ = writeln!(dot, "\treq{} -> req{} [label=\"{} ({:?})\"];", i, req, idx, kind);
+				Ok(())
+			});
+		}
+
+		dot.push_str("}\n");
+		dot
+	}
+}
+
+/// Collect requests into a builder.
+///
+/// Panics if any request references a non-existent or wrongly-typed output of a
+/// prior request. Use `push` or `push_batch` for fallible construction.
+impl<T: IncompleteRequest> FromIterator<T> for RequestBuilder<T> {
+	fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
+		let mut builder = RequestBuilder::default();
+		for (i, req) in iter.into_iter().enumerate() {
+			if let Err(e) = builder.push(req) {
+				panic!("request {} in collected chain has an invalid back-reference: {:?}", i, e);
+			}
+		}
+
+		builder
+	}
+}
+
+/// Extend the request chain.
+///
+/// Panics if any request references a non-existent or wrongly-typed output of a
+/// prior request. Use `try_extend` for fallible extension.
+impl<T: IncompleteRequest> Extend<T> for RequestBuilder<T> {
+	fn extend<I: IntoIterator<Item=T>>(&mut self, iter: I) {
+		for req in iter {
+			if let Err(e) = self.push(req) {
+				panic!("extending request chain with an invalid back-reference: {:?}", e);
+			}
 		}
 	}
 }
 
 /// Requests pending responses.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Requests<T> {
+///
+/// Like for `RequestBuilder`, equality only considers which requests are optional,
+/// not their skip conditions.
+#[derive(Debug, Clone)]
+pub struct Requests<T: IncompleteRequest> {
 	outputs: HashMap<(usize, usize), Output>,
+	output_kinds: HashMap<(usize, usize), OutputKind>,
+	back_refs: Vec<Vec<Option<(usize, usize)>>>, // back-references of each request as built.
 	requests: Vec<T>,
 	answered: usize,
+	deadlines: HashMap<usize, Instant>,
+	buffered: HashMap<usize, T::Response>, // responses received ahead of their turn.
+	fill_trace: Option<Vec<FillEvent>>, // only recorded when enabled.
+	priorities: Vec<u8>, // scheduling hints, one per request.
+	conditions: HashMap<usize, SkipPredicate>, // skip conditions of optional requests.
+	skipped: HashSet<usize>, // requests passed over without being sent.
+}
+
+impl<T: IncompleteRequest + PartialEq> PartialEq for Requests<T> where T::Response: PartialEq {
+	fn eq(&self, other: &Self) -> bool {
+		self.outputs == other.outputs
+			&& self.output_kinds == other.output_kinds
+			&& self.back_refs == other.back_refs
+			&& self.requests == other.requests
+			&& self.answered == other.answered
+			&& self.deadlines == other.deadlines
+			&& self.buffered == other.buffered
+			&& self.fill_trace == other.fill_trace
+			&& self.priorities == other.priorities
+			&& same_optional(&self.conditions, &other.conditions)
+			&& self.skipped == other.skipped
+	}
+}
+
+impl<T: IncompleteRequest + Eq> Eq for Requests<T> where T::Response: Eq {}
+
+/// Identity of an output independent of its position within a batch:
+/// the canonical encoding of the filled request producing it, and the
+/// output's index within that request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutputRef {
+	request: Vec<u8>,
+	output: usize,
+}
+
+impl OutputRef {
+	/// Create a reference to the given output of a request.
+	/// The request should have all of its fields filled.
+	pub fn new<T: Encodable>(request: &T, output: usize) -> Self {
+		OutputRef {
+			request: rlp::encode(request).to_vec(),
+			output: output,
+		}
+	}
+}
+
+/// Outputs resolved by any batch, shareable between batches.
+pub type OutputCache = HashMap<OutputRef, Output>;
+
+/// A single attempt to fill a back-reference, recorded when fill tracing is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FillEvent {
+	/// Index of the request whose field was being filled.
+	pub consuming_req: usize,
+	/// Index of the back-referencing field within that request, in fill order.
+	pub field_idx: usize,
+	/// The referenced output, as (request index, output index).
+	pub source: (usize, usize),
+	/// Whether the referenced output was available.
+	pub found: bool,
 }
 
-impl<T> Requests<T> {
+impl<T: IncompleteRequest> Requests<T> {
 	/// Get access to the underlying slice of requests.
 	// TODO: unimplemented -> Vec<Request>, // do we _have to_ allocate?
 	pub fn requests(&self) -> &[T] { &self.requests }
 
+	/// Get the requests which have not yet been answered.
+	pub fn pending(&self) -> &[T] { &self.requests[self.answered..] }
+
+	/// Get the requests which have already been answered.
+	pub fn answered_slice(&self) -> &[T] { &self.requests[..self.answered] }
+
+	/// Get a reference to the next unanswered request, without completing it.
+	/// Returns `None` when all requests answered.
+	pub fn peek_next(&self) -> Option<&T> { self.requests.get(self.answered) }
+
 	/// Get the number of answered requests.
 	pub fn num_answered(&self) -> usize { self.answered }
 
+	/// Get the outputs collected so far, keyed by (request index, output index).
+	pub fn collected_outputs(&self) -> &HashMap<(usize, usize), Output> { &self.outputs }
+
+	/// Whether the request at the given index was skipped, declined or cancelled rather than answered.
+	pub fn is_skipped(&self, idx: usize) -> bool { self.skipped.contains(&idx) }
+
+	/// Enable or disable recording of back-reference fill attempts made while
+	/// supplying responses. Disabling discards any recorded events.
+	pub fn set_fill_trace(&mut self, enabled: bool) {
+		self.fill_trace = if enabled { Some(Vec::new()) } else { None };
+	}
+
+	/// Get the fill attempts recorded so far. Empty when tracing is disabled.
+	pub fn fill_trace(&self) -> &[FillEvent] {
+		self.fill_trace.as_ref().map_or(&[], |trace| &trace[..])
+	}
+
+	/// Set the scheduling priority hint of the request at the given index.
+	/// Requests are still answered in order; the hint only tells the transport
+	/// how aggressively to pursue each outstanding request.
+	///
+	/// Panics if `idx` is out of bounds.
+	pub fn set_priority(&mut self, idx: usize, priority: u8) {
+		self.priorities[idx] = priority;
+	}
+
+	/// Get the scheduling priority hint of the request at the given index.
+	/// Defaults to zero.
+	pub fn priority(&self, idx: usize) -> u8 {
+		self.priorities.get(idx).cloned().unwrap_or(0)
+	}
+
+	/// Get the number of requests still awaiting a response.
+	pub fn num_pending(&self) -> usize { self.requests.len() - self.answered }
+
+	/// Get the fraction of requests answered. An empty batch is fully answered.
+	pub fn progress(&self) -> f64 {
+		if self.requests.is_empty() {
+			1.0
+		} else {
+			self.answered as f64 / self.requests.len() as f64
+		}
+	}
+
 	/// Whether the batch is complete.
 	pub fn is_complete(&self) -> bool {
 		self.answered == self.requests.len()
 	}
 
+	/// Whether both batches were built from the same chain of requests,
+	/// regardless of how far each has progressed.
+	pub fn same_chain(&self, other: &Self) -> bool where T: PartialEq + Clone {
+		self.originals() == other.originals()
+	}
+
+	/// Note that the next unanswered request has been sent, and must be
+	/// answered by the given deadline. Has no effect when all requests are answered.
+	pub fn mark_sent(&mut self, deadline: Instant) {
+		if !self.is_complete() {
+			self.deadlines.insert(self.answered, deadline);
+		}
+	}
+
+	/// Whether the next unanswered request has passed its deadline.
+	/// Requests which haven't been marked as sent never expire.
+	pub fn expired(&self, now: Instant) -> bool {
+		self.deadlines.get(&self.answered).map_or(false, |deadline| now >= *deadline)
+	}
+
+	/// Finish the batch without answering the remaining requests, which are
+	/// marked as skipped. Outputs collected so far are kept, but no further
+	/// responses are accepted.
+	pub fn cancel_remaining(&mut self) {
+		self.skipped.extend(self.answered..self.requests.len());
+		self.answered = self.requests.len();
+		self.deadlines.clear();
+		self.buffered.clear();
+	}
+
+	/// Convert a fully answered batch into its completed requests.
+	/// Skipped requests, including those cancelled before being answered, are omitted.
+	/// Returns `None` if any requests are unanswered.
+	pub fn into_complete(self) -> Option<Vec<T::Complete>> {
+		if !self.is_complete() { return None }
+
+		let skipped = self.skipped;
+		self.requests.into_iter()
+			.enumerate()
+			.filter(|&(idx, _)| !skipped.contains(&idx))
+			.map(|(_, req)| req.complete().ok())
+			.collect()
+	}
+
+	/// Convert the unanswered requests back into a builder, so that more
+	/// requests may be appended before dispatching them again.
+	/// References to outputs of answered requests are replaced with their values
+	/// and the remaining back-references are adjusted to start at zero.
+	///
+	/// Unanswered requests referencing outputs which will never be known, e.g. of
+	/// skipped or declined requests, are dropped along with their dependants.
+	pub fn into_builder(self) -> RequestBuilder<T> {
+		let answered = self.answered;
+		let outputs = self.outputs;
+		let mut conditions = self.conditions;
+		let mut builder = RequestBuilder::with_capacity(self.requests.len() - answered);
+		let mut new_idx = HashMap::new();
+
+		for (idx, mut req) in self.requests.into_iter().enumerate().skip(answered) {
+			req.fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput));
+			if req.check_outputs(|req_idx, _, _| new_idx.get(&req_idx).map(|_| ()).ok_or(NoSuchOutput)).is_err() {
+				continue;
+			}
+
+			req.adjust_refs(|req_idx| new_idx[&req_idx]);
+			builder.push(req).expect("outputs of answered requests are filled; remaining references point to retained requests; qed");
+			new_idx.insert(idx, builder.requests.len() - 1);
+			if let Some(skip_if) = conditions.remove(&idx) {
+				builder.conditions.insert(builder.requests.len() - 1, skip_if);
+			}
+		}
+
+		builder
+	}
+
 	/// Map requests from one type into another.
+	/// Buffered out-of-order responses are discarded.
 	pub fn map_requests<F, U>(self, f: F) -> Requests<U>
 		where F: FnMut(T) -> U, U: IncompleteRequest
 	{
 		Requests {
 			outputs: self.outputs,
+			output_kinds: self.output_kinds,
+			back_refs: self.back_refs,
 			requests: self.requests.into_iter().map(f).collect(),
 			answered: self.answered,
+			deadlines: self.deadlines,
+			buffered: HashMap::new(),
+			fill_trace: self.fill_trace,
+			priorities: self.priorities,
+			conditions: self.conditions,
+			skipped: self.skipped,
+		}
+	}
+
+	/// Map requests from one type into another, supplying the index of each
+	/// request within the chain. Buffered out-of-order responses are discarded.
+	pub fn map_requests_indexed<F, U>(self, mut f: F) -> Requests<U>
+		where F: FnMut(usize, T) -> U, U: IncompleteRequest
+	{
+		Requests {
+			outputs: self.outputs,
+			output_kinds: self.output_kinds,
+			back_refs: self.back_refs,
+			requests: self.requests.into_iter().enumerate().map(|(i, req)| f(i, req)).collect(),
+			answered: self.answered,
+			deadlines: self.deadlines,
+			buffered: HashMap::new(),
+			fill_trace: self.fill_trace,
+			priorities: self.priorities,
+			conditions: self.conditions,
+			skipped: self.skipped,
+		}
+	}
+
+	/// Fallibly map requests from one type into another.
+	/// Short-circuits on the first error. Buffered out-of-order responses are discarded.
+	pub fn try_map_requests<F, U, E>(self, f: F) -> Result<Requests<U>, E>
+		where F: FnMut(T) -> Result<U, E>, U: IncompleteRequest
+	{
+		Ok(Requests {
+			outputs: self.outputs,
+			output_kinds: self.output_kinds,
+			back_refs: self.back_refs,
+			requests: self.requests.into_iter().map(f).collect::<Result<_, _>>()?,
+			answered: self.answered,
+			deadlines: self.deadlines,
+			buffered: HashMap::new(),
+			fill_trace: self.fill_trace,
+			priorities: self.priorities,
+			conditions: self.conditions,
+			skipped: self.skipped,
+		})
+	}
+
+	// skip over optional requests whose condition holds against the collected outputs,
+	// along with requests referencing outputs of skipped requests.
+	fn skip_optional(&mut self) {
+		while !self.is_complete() && self.should_skip_next() {
+			self.skipped.insert(self.answered);
+			self.buffered.remove(&self.answered);
+			self.answered += 1;
+
+			let outputs = &self.outputs;
+			if let Some(ref mut req) = self.requests.get_mut(self.answered) {
+				req.fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput))
+			}
 		}
 	}
+
+	// whether the next request is optional and its condition holds,
+	// or it references outputs of a skipped request.
+	fn should_skip_next(&self) -> bool {
+		let skipped = &self.skipped;
+		let depends_on_skipped = self.requests[self.answered].check_outputs(|req_idx, _, _| {
+			if skipped.contains(&req_idx) { Err(NoSuchOutput) } else { Ok(()) }
+		}).is_err();
+
+		depends_on_skipped || self.conditions.get(&self.answered).map_or(false, |skip_if| skip_if(&self.outputs))
+	}
 }
 
 impl<T: IncompleteRequest + Clone> Requests<T> {
@@ -116,6 +821,61 @@ impl<T: IncompleteRequest + Clone> Requests<T> {
 		}
 	}
 
+	/// Get the outputs referenced by the next request which are not yet known.
+	/// The next request may be completed when this is empty.
+	pub fn next_dependencies(&self) -> Vec<(usize, usize)> {
+		let mut dependencies = Vec::new();
+		if let Some(req) = self.peek_next() {
+			let _ = req.check_outputs(|req_idx, out_idx, _| {
+				if !self.outputs.contains_key(&(req_idx, out_idx)) {
+					dependencies.push((req_idx, out_idx));
+				}
+				Ok(())
+			});
+		}
+
+		dependencies
+	}
+
+	/// Supply the value of a declared output ahead of the response which would
+	/// produce it, and fill all unanswered requests with it as necessary.
+	/// Fails if the output was never declared or has a different kind.
+	pub fn prefill_output(&mut self, key: (usize, usize), value: Output) -> Result<(), NoSuchOutput> {
+		match self.output_kinds.get(&key) {
+			Some(kind) if kind == &value.kind() => {},
+			_ => return Err(NoSuchOutput),
+		}
+
+		self.outputs.insert(key, value);
+		self.fill_unanswered();
+		self.skip_optional();
+		Ok(())
+	}
+
+	/// Reset the batch to its state as built, discarding all outputs,
+	/// fills and buffered responses so that it may be answered again from the start.
+	pub fn reset(&mut self) {
+		self.outputs.clear();
+		self.deadlines.clear();
+		self.buffered.clear();
+		if let Some(ref mut trace) = self.fill_trace { trace.clear() }
+		for (req, refs) in self.requests.iter_mut().zip(&self.back_refs) {
+			req.restore_back_references(refs);
+		}
+		self.answered = 0;
+		self.skipped.clear();
+		self.skip_optional();
+	}
+
+	// the requests as built, without any fills.
+	fn originals(&self) -> Vec<T> {
+		self.requests.iter().zip(&self.back_refs).map(|(req, refs)| {
+			let mut req = req.clone();
+			req.restore_back_references(refs);
+			req
+		}).collect()
+	}
+
 	/// Sweep through all unanswered requests, filling them as necessary.
 	pub fn fill_unanswered(&mut self) {
 		let outputs = &mut self.outputs;
@@ -126,19 +886,59 @@ impl<T: IncompleteRequest + Clone> Requests<T> {
 	}
 }
 
+impl<T: IncompleteRequest + Clone + Encodable> Requests<T> {
+	/// Store the outputs of answered requests in the shared cache.
+	pub fn export_outputs(&self, cache: &mut OutputCache) {
+		for (&(req_idx, out_idx), output) in &self.outputs {
+			if req_idx < self.answered {
+				cache.insert(OutputRef::new(&self.requests[req_idx], out_idx), output.clone());
+			}
+		}
+	}
+
+	/// Supply outputs of unanswered requests from the shared cache, filling
+	/// all unanswered requests with them as necessary. Only outputs of
+	/// requests without unresolved back-references can be looked up.
+	/// Leading optional requests whose condition now holds are skipped.
+	pub fn fill_from(&mut self, cache: &OutputCache) {
+		for req_idx in self.answered..self.requests.len() {
+			{
+				let outputs = &self.outputs;
+				self.requests[req_idx].fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput));
+			}
+
+			if self.requests[req_idx].clone().complete().is_err() { continue }
+
+			let mut out_indices = Vec::new();
+			self.requests[req_idx].note_outputs(|out_idx, _| out_indices.push(out_idx));
+			for out_idx in out_indices {
+				if let Some(output) = cache.get(&OutputRef::new(&self.requests[req_idx], out_idx)) {
+					self.outputs.insert((req_idx, out_idx), output.clone());
+				}
+			}
+		}
+
+		self.skip_optional();
+	}
+}
+
 impl<T: super::CheckedRequest> Requests<T> {
 	/// Supply a response for the next request.
 	/// Fails on: wrong request kind, all requests answered already.
+	/// The error carries the index of the next request at the time of failure.
+	///
+	/// Failure is non-fatal: a rejected response leaves the batch untouched,
+	/// so the same request may be retried with a response from another peer.
 	pub fn supply_response(&mut self, env: &T::Environment, response: &T::Response)
-		-> Result<T::Extract, ResponseError<T::Error>>
+		-> Result<T::Extract, IndexedResponseError<T::Error>>
 	{
 		let idx = self.answered;
 
 		// check validity.
-		if self.is_complete() { return Err(ResponseError::Unexpected) }
+		if self.is_complete() { return Err(ResponseError::Unexpected.at(idx)) }
 
 		let extracted = self.requests[idx]
-			.check_response(env, response).map_err(ResponseError::Validity)?;
+			.check_response(env, response).map_err(|e| ResponseError::Validity(e).at(idx))?;
 
 		let outputs = &mut self.outputs;
 		response.fill_outputs(|out_idx, output| {
@@ -151,42 +951,483 @@ impl<T: super::CheckedRequest> Requests<T> {
 		self.answered += 1;
 
 		// fill as much of the next request as we can.
-		if let Some(ref mut req) = self.requests.get_mut(self.answered) {
-			req.fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput))
+		let consuming_req = self.answered;
+		if let Some(ref mut req) = self.requests.get_mut(consuming_req) {
+			match self.fill_trace {
+				None => req.fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput)),
+				Some(ref mut trace) => {
+					// the oracle is `Fn`, so record events through a `RefCell`.
+					let events = RefCell::new(Vec::new());
+					req.fill(|req_idx, out_idx| {
+						let output = outputs.get(&(req_idx, out_idx)).cloned();
+						let mut events = events.borrow_mut();
+						let field_idx = events.len();
+						events.push(FillEvent {
+							consuming_req: consuming_req,
+							field_idx: field_idx,
+							source: (req_idx, out_idx),
+							found: output.is_some(),
+						});
+						output.ok_or(NoSuchOutput)
+					});
+					trace.extend(events.into_inner());
+				}
+			}
 		}
 
+		self.skip_optional();
 		Ok(extracted)
 	}
-}
 
-impl Requests<super::Request> {
-	/// For each request, produce a response.
-	/// The responses vector produced goes up to the point where the responder
-	/// first returns `None`, an invalid response, or until all requests have been responded to.
-	pub fn respond_to_all<F>(mut self, responder: F) -> Vec<super::Response>
+	/// Supply a response for the request at the given index, which may be ahead
+	/// of the next unanswered one. Responses for later requests are buffered until
+	/// all requests before them are answered, so that back-references are still
+	/// filled in chain order. Yields the data extracted from every response applied.
+	/// Fails on: wrong request kind for the next request or for a buffered response
+	/// once its request is reached, index already answered, already buffered or out of bounds.
+	/// The error carries the index of the request the failing response was supplied for,
+	/// along with the data extracted from the responses applied before it.
+	///
+	/// An invalid buffered response is discarded, leaving its request unanswered, while
+	/// responses applied before it remain applied and those buffered after it are kept.
+	pub fn supply_response_at(&mut self, idx: usize, env: &T::Environment, response: T::Response)
+		-> Result<Vec<T::Extract>, (Vec<T::Extract>, IndexedResponseError<T::Error>)>
+	{
+		if idx < self.answered || idx >= self.requests.len() || self.buffered.contains_key(&idx) {
+			return Err((Vec::new(), ResponseError::Unexpected.at(idx)));
+		}
+
+		if idx > self.answered {
+			self.buffered.insert(idx, response);
+			return Ok(Vec::new());
+		}
+
+		let mut extracted = vec![self.supply_response(env, &response).map_err(|e| (Vec::new(), e))?];
+		while let Some(response) = self.buffered.remove(&self.answered) {
+			match self.supply_response(env, &response) {
+				Ok(extract) => extracted.push(extract),
+				Err(e) => return Err((extracted, e)),
+			}
+		}
+
+		Ok(extracted)
+	}
+
+	/// Supply responses for the next requests, in order.
+	/// Stops at the first failure, yielding the index of the offending response.
+	/// Responses supplied prior to the failure remain applied.
+	pub fn supply_responses(&mut self, env: &T::Environment, responses: &[T::Response])
+		-> Result<Vec<T::Extract>, (usize, ResponseError<T::Error>)>
+	{
+		let mut extracted = Vec::with_capacity(responses.len());
+		for (i, response) in responses.iter().enumerate() {
+			extracted.push(self.supply_response(env, response).map_err(|e| (i, e.error))?);
+		}
+
+		Ok(extracted)
+	}
+}
+
+/// Reason for which `respond_to_all_with_reason` stopped producing responses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Completion {
+	/// All requests have been responded to.
+	Complete,
+	/// The responder halted the loop.
+	ResponderStopped,
+	/// The maximal number of responses has been produced.
+	LimitReached,
+	/// The responder produced an invalid response.
+	BadResponse(ResponseError<WrongKind>),
+}
+
+/// Summary statistics of a `Requests` chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchSummary {
+	/// Total number of requests.
+	pub total: usize,
+	/// Number of answered requests.
+	pub answered: usize,
+	/// Number of requests still awaiting a response.
+	pub pending: usize,
+	/// Number of requests of each kind, keyed by the kind's name.
+	pub kinds: HashMap<&'static str, usize>,
+}
+
+/// What a responder passed to `respond_to_all_controlled` chooses to do with a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Respond {
+	/// Answer the request with the given response.
+	Answer(super::Response),
+	/// Leave this request unanswered, but carry on with the following ones.
+	/// Requests depending on outputs of a declined request are declined as well.
+	Decline,
+	/// Stop responding altogether.
+	Halt,
+}
+
+impl From<Option<super::Response>> for Respond {
+	fn from(response: Option<super::Response>) -> Self {
+		match response {
+			Some(response) => Respond::Answer(response),
+			None => Respond::Halt,
+		}
+	}
+}
+
+impl Requests<super::Request> {
+	/// Summarize the chain: total, answered and pending requests, and
+	/// the number of requests of each kind.
+	pub fn summary(&self) -> BatchSummary {
+		let mut kinds = HashMap::new();
+		for req in &self.requests {
+			*kinds.entry(req.kind().name()).or_insert(0) += 1;
+		}
+
+		BatchSummary {
+			total: self.requests.len(),
+			answered: self.answered,
+			pending: self.requests.len() - self.answered,
+			kinds: kinds,
+		}
+	}
+
+	/// For each request, produce a response.
+	/// The responses vector produced goes up to the point where the responder
+	/// first returns `None`, an invalid response, or until all requests have been responded to.
+	/// An empty batch is already complete: the responder is never invoked and no responses are produced.
+	pub fn respond_to_all<F>(self, responder: F) -> Vec<super::Response>
+		where F: Fn(super::CompleteRequest) -> Option<super::Response>
+	{
+		self.respond_to_all_with(responder, |_, _| {})
+	}
+
+	/// Like `respond_to_all_with_reason`, but the responder may decline individual
+	/// requests without halting. Note that the responses vector then no longer lines up
+	/// with the requests positionally.
+	pub fn respond_to_all_controlled<F>(self, responder: F) -> (Vec<super::Response>, Completion)
+		where F: Fn(super::CompleteRequest) -> Respond
+	{
+		self.respond_and_report(responder, |_, _| {}, usize::MAX)
+	}
+
+	/// Like `respond_to_all`, but stops after `max` successful responses,
+	/// even if more requests remain and the responder would have continued.
+	pub fn respond_to_all_limited<F>(self, responder: F, max: usize) -> Vec<super::Response>
+		where F: Fn(super::CompleteRequest) -> Option<super::Response>
+	{
+		self.respond_and_report(|req| responder(req).into(), |_, _| {}, max).0
+	}
+
+	/// Like `respond_to_all`, but invokes `on_progress` with the number of
+	/// answered requests and the total number of requests after each response is supplied.
+	pub fn respond_to_all_with<F, G>(self, responder: F, on_progress: G) -> Vec<super::Response>
+		where F: Fn(super::CompleteRequest) -> Option<super::Response>, G: FnMut(usize, usize)
+	{
+		self.respond_and_report(|req| responder(req).into(), on_progress, usize::MAX).0
+	}
+
+	/// Compute the total cost of sending the batch under the given cost table,
+	/// including the base cost of the packet.
+	pub fn total_cost(&self, table: &CostTable) -> U256 {
+		self.requests.iter().fold(table.base_cost(), |cost, req| cost + table.compute_cost(req))
+	}
+
+	/// Like `respond_to_all`, but additionally yields the reason the responses
+	/// vector ends where it does.
+	pub fn respond_to_all_with_reason<F>(self, responder: F) -> (Vec<super::Response>, Completion)
 		where F: Fn(super::CompleteRequest) -> Option<super::Response>
+	{
+		self.respond_and_report(|req| responder(req).into(), |_, _| {}, usize::MAX)
+	}
+
+	fn respond_and_report<F, G>(mut self, responder: F, mut on_progress: G, max: usize) -> (Vec<super::Response>, Completion)
+		where F: Fn(super::CompleteRequest) -> Respond, G: FnMut(usize, usize)
 	{
 		let mut responses = Vec::new();
 
-		while let Some(response) = self.next_complete().and_then(&responder) {
+		while !self.is_complete() {
+			if responses.len() >= max {
+				return (responses, Completion::LimitReached);
+			}
+
+			// requests referencing outputs of declined requests are skipped along with them.
+			let complete = self.requests[self.answered].clone().complete()
+				.expect("All outputs checked as invariant of `Requests` object; qed");
+
+			let response = match responder(complete) {
+				Respond::Answer(response) => response,
+				Respond::Decline => {
+					self.skip_next();
+					continue;
+				}
+				Respond::Halt => return (responses, Completion::ResponderStopped),
+			};
+
 			match self.supply_response(&(), &response) {
-				Ok(()) => responses.push(response),
+				Ok(()) => {
+					responses.push(response);
+					on_progress(self.answered, self.requests.len());
+				}
 				Err(e) => {
-					debug!(target: "pip", "produced bad response to request: {:?}", e);
-					return responses;
+					debug!(target: "pip", "produced bad response to {}", e);
+					return (responses, Completion::BadResponse(e.error));
 				}
 			}
 		}
 
-		responses
+		(responses, Completion::Complete)
+	}
+
+	// move on to the next request without answering the current one.
+	fn skip_next(&mut self) {
+		self.skipped.insert(self.answered);
+		self.buffered.remove(&self.answered);
+		self.answered += 1;
+
+		let outputs = &self.outputs;
+		if let Some(ref mut req) = self.requests.get_mut(self.answered) {
+			req.fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput))
+		}
+
+		self.skip_optional();
+	}
+}
+
+#[cfg(feature = "serialize")]
+mod serialization {
+	use std::collections::HashMap;
+	use rlp::{self, Encodable, Decodable, UntrustedRlp};
+	use serde::{Serialize, Serializer, Deserialize, Deserializer};
+	use serde::ser::Error as SerError;
+	use serde::de::Error as DeError;
+	use request::{IncompleteRequest, NoSuchOutput, Output};
+	use super::{RequestBuilder, Requests};
+
+	// outputs are keyed by `(usize, usize)` which can't be used as e.g. a JSON
+	// object key, and requests are stored in their canonical RLP encoding.
+	// skip conditions of optional requests are functions and can't be stored,
+	// so only batches which have reached all of their optional requests are
+	// serializable.
+	#[derive(Serialize, Deserialize)]
+	struct SerializableRequests {
+		outputs: Vec<((usize, usize), SerializableOutput)>,
+		originals: Vec<Vec<u8>>,
+		requests: Vec<Vec<u8>>,
+		answered: usize,
+		#[serde(default)]
+		skipped: Vec<usize>,
+		#[serde(default)]
+		priorities: Vec<u8>,
+	}
+
+	#[derive(Serialize, Deserialize)]
+	enum SerializableOutput {
+		Hash([u8; 32]),
+		Number(u64),
+	}
+
+	impl<T: IncompleteRequest + Encodable + Clone> Serialize for Requests<T> {
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			if self.conditions.keys().any(|&idx| idx >= self.answered) {
+				return Err(S::Error::custom("skip conditions of unanswered optional requests can't be serialized"));
+			}
+
+			SerializableRequests {
+				outputs: self.outputs.iter().map(|(&key, output)| (key, match *output {
+					Output::Hash(ref hash) => SerializableOutput::Hash(hash.0),
+					Output::Number(num) => SerializableOutput::Number(num),
+				})).collect(),
+				originals: self.originals().iter().map(|req| rlp::encode(req).to_vec()).collect(),
+				requests: self.requests.iter().map(|req| rlp::encode(req).to_vec()).collect(),
+				answered: self.answered,
+				skipped: self.skipped.iter().cloned().collect(),
+				priorities: self.priorities.clone(),
+			}.serialize(serializer)
+		}
+	}
+
+	impl<T: IncompleteRequest + Decodable> Deserialize for Requests<T> {
+		fn deserialize<D: Deserializer>(deserializer: D) -> Result<Self, D::Error> {
+			let raw = SerializableRequests::deserialize(deserializer)?;
+
+			let decode = |encoded: &[Vec<u8>]| encoded.iter()
+				.map(|bytes| UntrustedRlp::new(bytes).as_val())
+				.collect::<Result<Vec<T>, _>>()
+				.map_err(|e| D::Error::custom(format!("invalid request encoding: {}", e)));
+
+			let originals = decode(&raw.originals[..])?;
+			let mut requests = decode(&raw.requests[..])?;
+
+			if originals.len() != requests.len() {
+				return Err(D::Error::custom("mismatched number of original requests"));
+			}
+
+			if raw.answered > requests.len() {
+				return Err(D::Error::custom("more requests answered than present"));
+			}
+
+			if raw.skipped.iter().any(|&idx| idx >= raw.answered) {
+				return Err(D::Error::custom("skipped request not yet reached"));
+			}
+
+			let priorities = match raw.priorities.len() {
+				0 => vec![0; requests.len()],
+				len if len == requests.len() => raw.priorities,
+				_ => return Err(D::Error::custom("mismatched number of priorities")),
+			};
+
+			// replay the chain as built, so that back-references are checked
+			// just as they would have been by the builder.
+			let back_refs: Vec<_> = originals.iter().map(|req| req.back_references()).collect();
+			let mut builder = RequestBuilder::with_capacity(originals.len());
+			for (req_idx, req) in originals.into_iter().enumerate() {
+				builder.push(req).map_err(|e| D::Error::custom(format!(
+					"request {} references unknown output {} of request {}", req_idx, e.idx, e.req)))?;
+			}
+
+			// requests may only differ from their originals by fills.
+			for (req, refs) in requests.iter().zip(&back_refs) {
+				let filled_refs = req.back_references();
+				let consistent = filled_refs.len() == refs.len() && filled_refs.iter().zip(refs)
+					.all(|(filled, original)| filled.is_none() || filled == original);
+
+				if !consistent {
+					return Err(D::Error::custom("request doesn't match the request it was built as"));
+				}
+			}
+
+			let output_kinds = builder.output_kinds;
+			let mut outputs = HashMap::with_capacity(raw.outputs.len());
+			for (key, output) in raw.outputs {
+				let output = match output {
+					SerializableOutput::Hash(hash) => Output::Hash(hash.into()),
+					SerializableOutput::Number(num) => Output::Number(num),
+				};
+
+				if output_kinds.get(&key) != Some(&output.kind()) {
+					return Err(D::Error::custom(format!("undeclared output {} of request {}", key.1, key.0)));
+				}
+
+				outputs.insert(key, output);
+			}
+
+			// the next request must be answerable from the collected outputs.
+			if let Some(req) = requests.get_mut(raw.answered) {
+				req.fill(|req_idx, out_idx| outputs.get(&(req_idx, out_idx)).cloned().ok_or(NoSuchOutput));
+				if req.check_outputs(|_, _, _| Err(NoSuchOutput)).is_err() {
+					return Err(D::Error::custom("next request references outputs which are not known"));
+				}
+			}
+
+			Ok(Requests {
+				outputs: outputs,
+				output_kinds: output_kinds,
+				back_refs: back_refs,
+				requests: requests,
+				answered: raw.answered,
+				deadlines: HashMap::new(),
+				buffered: HashMap::new(),
+				fill_trace: None,
+				priorities: priorities,
+				conditions: HashMap::new(),
+				skipped: raw.skipped.into_iter().collect(),
+			})
+		}
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use std::collections::HashMap;
 	use request::*;
-	use super::RequestBuilder;
-	use util::H256;
+	use super::{RequestBuilder, RequestStream, Completion, PushError, FillEvent, OutputCache, Optional, SkipPredicate};
+	use util::{H256, U256};
+
+	// a header proof request, followed by a receipts request for the hash it yields.
+	fn proof_and_receipts() -> RequestBuilder<Request> {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		})).unwrap();
+		builder
+	}
+
+	// a request for a single hash, possibly referencing an earlier one.
+	// unlike the network requests, its response may withhold the hash it yields.
+	#[derive(Debug, Clone)]
+	struct HashRequest(Field<H256>);
+
+	struct HashResponse(Option<H256>);
+
+	impl ResponseLike for HashResponse {
+		fn fill_outputs<F>(&self, mut f: F) where F: FnMut(usize, Output) {
+			if let Some(hash) = self.0 {
+				f(0, Output::Hash(hash));
+			}
+		}
+	}
+
+	impl IncompleteRequest for HashRequest {
+		type Complete = H256;
+		type Response = HashResponse;
+
+		fn check_outputs<F>(&self, mut f: F) -> Result<(), NoSuchOutput>
+			where F: FnMut(usize, usize, OutputKind) -> Result<(), NoSuchOutput>
+		{
+			match self.0 {
+				Field::BackReference(req, idx) => f(req, idx, OutputKind::Hash),
+				Field::Scalar(_) => Ok(()),
+			}
+		}
+
+		fn note_outputs<F>(&self, mut f: F) where F: FnMut(usize, OutputKind) {
+			f(0, OutputKind::Hash);
+		}
+
+		fn fill<F>(&mut self, oracle: F) where F: Fn(usize, usize) -> Result<Output, NoSuchOutput> {
+			if let Field::BackReference(req, idx) = self.0 {
+				if let Ok(Output::Hash(hash)) = oracle(req, idx) {
+					self.0 = Field::Scalar(hash);
+				}
+			}
+		}
+
+		fn complete(self) -> Result<H256, NoSuchOutput> {
+			self.0.into_scalar()
+		}
+
+		fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
+			self.0.adjust_req(mapping)
+		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.0.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.0.restore(refs[0]);
+		}
+	}
+
+	impl CheckedRequest for HashRequest {
+		type Extract = ();
+		type Error = ();
+		type Environment = ();
+
+		fn check_response(&self, _: &(), _: &HashResponse) -> Result<(), ()> {
+			Ok(())
+		}
+	}
+
+	// stands in for checking the header's transactions root: the block hashed
+	// to `1` is known to carry no transactions.
+	fn no_transactions(outputs: &HashMap<(usize, usize), Output>) -> bool {
+		outputs.get(&(0, 0)) == Some(&Output::Hash(H256::from(1)))
+	}
 
 	#[test]
 	fn all_scalar() {
@@ -200,24 +1441,22 @@ mod tests {
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_backref() {
 		let mut builder = RequestBuilder::default();
-		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+		assert_eq!(builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
 			num: Field::BackReference(100, 3),
-		})).unwrap();
+		})), Err(InvalidBackReference { req: 100, idx: 3 }));
 	}
 
 	#[test]
-	#[should_panic]
 	fn wrong_kind() {
 		let mut builder = RequestBuilder::default();
 		assert!(builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
 			num: 100.into(),
 		})).is_ok());
-		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+		assert_eq!(builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
 			num: Field::BackReference(0, 0),
-		})).unwrap();
+		})), Err(InvalidBackReference { req: 0, idx: 0 }));
 	}
 
 	#[test]
@@ -230,4 +1469,1364 @@ mod tests {
 			hash: Field::BackReference(0, 0),
 		})).unwrap();
 	}
+
+	#[test]
+	fn with_capacity_preallocates() {
+		let mut builder = RequestBuilder::with_capacity(16);
+		assert!(builder.requests.capacity() >= 16);
+
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+
+		let requests = builder.build();
+		assert_eq!(requests.requests().len(), 1);
+	}
+
+	#[test]
+	fn push_batch_reports_failing_index() {
+		let mut builder = RequestBuilder::default();
+		let res = builder.push_batch(vec![
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(5, 0),
+			}),
+		]);
+
+		assert_eq!(res, Err((2, InvalidBackReference { req: 5, idx: 0 })));
+		assert_eq!(builder.output_kinds().len(), 1);
+		assert_eq!(builder.build().requests().len(), 2);
+	}
+
+	#[test]
+	fn pop_erases_outputs() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+
+		assert_eq!(builder.output_kinds().len(), 2);
+		assert!(builder.pop().is_some());
+		assert_eq!(builder.output_kinds().len(), 1);
+		assert!(builder.output_kinds().contains_key(&(0, 0)));
+
+		assert!(builder.pop().is_some());
+		assert!(builder.pop().is_none());
+		assert!(builder.output_kinds().is_empty());
+	}
+
+	#[test]
+	fn collect_into_builder() {
+		let builder: RequestBuilder<_> = vec![
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+		].into_iter().collect();
+
+		assert_eq!(builder.build().requests().len(), 2);
+	}
+
+	#[test]
+	#[should_panic]
+	fn collect_bad_backref() {
+		let _: RequestBuilder<_> = vec![
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+		].into_iter().collect();
+	}
+
+	#[test]
+	fn try_map_requests_short_circuits() {
+		let requests = proof_and_receipts().build();
+		let mapped = requests.clone().try_map_requests(|req| -> Result<_, ()> { Ok(req) });
+		assert_eq!(mapped, Ok(requests.clone()));
+
+		let failed = requests.try_map_requests(|req| match req {
+			Request::Receipts(_) => Err(()),
+			req => Ok(req),
+		});
+		assert_eq!(failed, Err(()));
+	}
+
+	#[test]
+	fn pending_and_progress() {
+		let empty: Requests<Request> = RequestBuilder::default().build();
+		assert_eq!(empty.num_pending(), 0);
+		assert_eq!(empty.progress(), 1.0);
+
+		let mut requests = proof_and_receipts().build();
+		assert_eq!(requests.num_pending(), 2);
+		assert_eq!(requests.progress(), 0.0);
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: Vec::new(),
+			hash: H256::default(),
+			td: 0.into(),
+		})).unwrap();
+		assert_eq!(requests.num_pending(), 1);
+		assert_eq!(requests.progress(), 0.5);
+	}
+
+	#[test]
+	fn bad_response_is_not_fatal() {
+		let mut requests = proof_and_receipts().build();
+		let before = requests.clone();
+
+		let bad = Response::Receipts(ReceiptsResponse { receipts: vec![] });
+		assert_eq!(requests.supply_response(&(), &bad), Err(ResponseError::Validity(WrongKind).at(0)));
+		assert_eq!(requests, before);
+
+		let good = Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		});
+		assert_eq!(requests.supply_response(&(), &good), Ok(()));
+		assert_eq!(requests.num_answered(), 1);
+	}
+
+	#[test]
+	fn respond_to_all_reasons() {
+		let build = || proof_and_receipts().build();
+
+		let good = |req| Some(match req {
+			CompleteRequest::HeaderProof(_) => Response::HeaderProof(HeaderProofResponse {
+				proof: vec![],
+				hash: H256::default(),
+				td: 100.into(),
+			}),
+			_ => Response::Receipts(ReceiptsResponse { receipts: vec![] }),
+		});
+
+		let (responses, reason) = build().respond_to_all_with_reason(&good);
+		assert_eq!(responses.len(), 2);
+		assert_eq!(reason, Completion::Complete);
+
+		let (responses, reason) = build().respond_to_all_with_reason(|req| match req {
+			CompleteRequest::HeaderProof(_) => good(req),
+			_ => None,
+		});
+		assert_eq!(responses.len(), 1);
+		assert_eq!(reason, Completion::ResponderStopped);
+
+		let (responses, reason) = build().respond_to_all_with_reason(|_|
+			Some(Response::Receipts(ReceiptsResponse { receipts: vec![] }))
+		);
+		assert!(responses.is_empty());
+		assert_eq!(reason, Completion::BadResponse(ResponseError::Validity(WrongKind)));
+	}
+
+	#[test]
+	#[cfg(feature = "serialize")]
+	fn serde_roundtrip() {
+		use serde_json;
+
+		let mut requests = proof_and_receipts().build();
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: 5.into(),
+			td: 100.into(),
+		})).unwrap();
+
+		requests.set_priority(1, 3);
+
+		let json = serde_json::to_string(&requests).unwrap();
+		let decoded: Requests<Request> = serde_json::from_str(&json).unwrap();
+
+		assert_eq!(decoded, requests);
+		assert_eq!(decoded.priority(1), 3);
+		assert_eq!(decoded.next_complete(), Some(CompleteRequest::Receipts(CompleteReceiptsRequest {
+			hash: 5.into(),
+		})));
+	}
+
+	#[test]
+	#[cfg(feature = "serialize")]
+	fn serde_rejects_inconsistent_state() {
+		use serde_json::{self, Value};
+
+		let builder = proof_and_receipts();
+
+		let valid: Value = serde_json::from_str(&serde_json::to_string(&builder.build()).unwrap()).unwrap();
+		let decode = |value: &Value| serde_json::from_str::<Requests<Request>>(&serde_json::to_string(value).unwrap());
+		let with = |key: &str, field: Value| {
+			let mut value = valid.clone();
+			value.as_object_mut().unwrap().insert(key.into(), field);
+			value
+		};
+
+		assert!(decode(&valid).is_ok());
+
+		// the next request can't be filled without the first response.
+		let unanswered = with("answered", Value::U64(1));
+		assert!(decode(&unanswered).is_err());
+
+		// outputs must be declared with the same kind.
+		let mut wrong_kind = unanswered.clone();
+		wrong_kind.as_object_mut().unwrap().insert("outputs".into(), serde_json::from_str("[[[0, 0], {\"Number\": 5}]]").unwrap());
+		assert!(decode(&wrong_kind).is_err());
+
+		// back-references are checked as they would be by the builder.
+		let mut originals = valid.as_object().unwrap().get("originals").unwrap().as_array().unwrap().clone();
+		originals.reverse();
+		assert!(decode(&with("originals", Value::Array(originals))).is_err());
+	}
+
+	#[test]
+	#[cfg(feature = "serialize")]
+	fn serde_refuses_pending_skip_conditions() {
+		use serde_json;
+
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push_optional(Optional::new(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		}), no_transactions)).unwrap();
+
+		let mut requests = builder.build();
+		assert!(serde_json::to_string(&requests).is_err());
+
+		requests.cancel_remaining();
+		assert!(serde_json::to_string(&requests).is_ok());
+	}
+
+	#[test]
+	fn unused_outputs() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		})).unwrap();
+
+		assert_eq!(builder.unused_outputs(), vec![(1, 0)]);
+	}
+
+	#[test]
+	fn total_cost() {
+		use net::request_credits::CostTable;
+
+		let builder = proof_and_receipts();
+
+		// base of 100000, header proof of 15000, receipts of 5000.
+		let expected: U256 = 120000.into();
+		assert_eq!(builder.build().total_cost(&CostTable::default()), expected);
+	}
+
+	#[test]
+	fn pending_and_answered_split() {
+		let mut requests = proof_and_receipts().build();
+		assert!(requests.answered_slice().is_empty());
+		assert_eq!(requests.pending().len(), 2);
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		})).unwrap();
+
+		assert_eq!(requests.answered_slice(), &requests.requests()[..1]);
+		assert_eq!(requests.pending(), &requests.requests()[1..]);
+	}
+
+	#[test]
+	fn push_dedup() {
+		let mut builder = RequestBuilder::default();
+		let proof = Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		});
+
+		assert_eq!(builder.push_dedup(proof.clone()), Ok(0));
+		assert_eq!(builder.push_dedup(proof), Ok(0));
+
+		let other_proof = Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		});
+		assert_eq!(builder.push_dedup(other_proof), Ok(1));
+
+		let receipts = |req| Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(req, 0),
+		});
+
+		assert_eq!(builder.push_dedup(receipts(0)), Ok(2));
+		assert_eq!(builder.push_dedup(receipts(0)), Ok(2));
+		assert_eq!(builder.push_dedup(receipts(1)), Ok(3));
+		assert_eq!(builder.push_dedup(receipts(3)), Err(InvalidBackReference { req: 3, idx: 0 }));
+
+		assert_eq!(builder.build().requests().len(), 4);
+	}
+
+	#[test]
+	fn peek_next() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		assert_eq!(requests.peek_next().map(|req| req.kind()), Some(Kind::HeaderProof));
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		})).unwrap();
+		assert!(requests.peek_next().is_none());
+	}
+
+	#[test]
+	fn insert_shifts_backreferences() {
+		let mut builder = proof_and_receipts();
+
+		builder.insert(0, Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 50.into(),
+		})).unwrap();
+
+		// may only reference outputs of prior requests.
+		assert_eq!(builder.insert(1, Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(1, 0),
+		})), Err(InvalidBackReference { req: 1, idx: 0 }));
+
+		builder.insert(1, Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		})).unwrap();
+
+		assert_eq!(builder.output_kinds().len(), 2);
+		assert!(builder.output_kinds().contains_key(&(0, 0)));
+		assert!(builder.output_kinds().contains_key(&(2, 0)));
+
+		let requests = builder.build();
+		assert_eq!(requests.requests(), &[
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 50.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(2, 0),
+			}),
+		][..]);
+	}
+
+	#[test]
+	fn outputs_of() {
+		let builder = proof_and_receipts();
+
+		assert_eq!(builder.outputs_of(0), vec![(0, OutputKind::Hash)]);
+		assert!(builder.outputs_of(1).is_empty());
+		assert!(builder.outputs_of(2).is_empty());
+	}
+
+	#[test]
+	fn prefill_output() {
+		let mut requests = proof_and_receipts().build();
+		assert_eq!(requests.prefill_output((0, 1), Output::Hash(5.into())), Err(NoSuchOutput));
+		assert_eq!(requests.prefill_output((0, 0), Output::Number(5)), Err(NoSuchOutput));
+		assert_eq!(requests.prefill_output((0, 0), Output::Hash(5.into())), Ok(()));
+
+		assert_eq!(requests.requests()[1], Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::Scalar(5.into()),
+		}));
+	}
+
+	#[test]
+	fn retain() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(1, 0),
+		})).unwrap();
+
+		let before = builder.clone();
+		assert_eq!(builder.retain(|req| match *req {
+			Request::HeaderProof(ref req) => req.num == Field::Scalar(100),
+			_ => true,
+		}), Err(InvalidBackReference { req: 1, idx: 0 }));
+		assert_eq!(builder, before);
+
+		builder.retain(|req| match *req {
+			Request::HeaderProof(ref req) => req.num == Field::Scalar(200),
+			_ => true,
+		}).unwrap();
+
+		assert_eq!(builder.output_kinds().len(), 1);
+		assert_eq!(builder.build().requests(), &[
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 200.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+		][..]);
+	}
+
+	#[test]
+	fn reset() {
+		let mut requests = proof_and_receipts().build();
+		let built = requests.clone();
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: 5.into(),
+			td: 100.into(),
+		})).unwrap();
+		assert!(requests != built);
+
+		requests.reset();
+		assert_eq!(requests, built);
+		assert_eq!(requests.num_answered(), 0);
+
+		// back-references survive mapping.
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: 5.into(),
+			td: 100.into(),
+		})).unwrap();
+
+		let mut mapped = requests.map_requests(|req| req);
+		mapped.reset();
+		assert_eq!(mapped, built);
+	}
+
+	#[test]
+	fn to_dot() {
+		let builder = proof_and_receipts();
+
+		assert_eq!(builder.to_dot(), "digraph requests {\n\
+			\treq0 [label=\"0: HeaderProof\"];\n\
+			\treq1 [label=\"1: Receipts\"];\n\
+			\treq1 -> req0 [label=\"0 (Hash)\"];\n\
+			}\n");
+	}
+
+	#[test]
+	fn supply_responses() {
+		let mut requests = proof_and_receipts().build();
+		let proof_response = Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		});
+
+		assert_eq!(
+			requests.supply_responses(&(), &[proof_response.clone(), proof_response]),
+			Err((1, ResponseError::Validity(WrongKind)))
+		);
+		assert_eq!(requests.num_answered(), 1);
+
+		let receipts_response = Response::Receipts(ReceiptsResponse { receipts: vec![] });
+		assert_eq!(requests.supply_responses(&(), &[receipts_response]), Ok(vec![()]));
+		assert!(requests.is_complete());
+	}
+
+	#[test]
+	fn outputs_by_kind() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push(Request::Account(IncompleteAccountRequest {
+			block_hash: Field::BackReference(0, 0),
+			address_hash: H256::default().into(),
+		})).unwrap();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+
+		// no request yields numbers, so only hashes are grouped.
+		let by_kind = builder.outputs_by_kind();
+		assert_eq!(by_kind.len(), 1);
+		assert_eq!(by_kind[&OutputKind::Hash], vec![(0, 0), (1, 0), (1, 1), (2, 0)]);
+		assert!(by_kind.get(&OutputKind::Number).is_none());
+	}
+
+	#[test]
+	fn try_extend() {
+		let mut builder = RequestBuilder::default();
+		builder.extend(vec![
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			}),
+		]);
+
+		let res = builder.try_extend(vec![
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(1, 0),
+			}),
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 200.into(),
+			}),
+		]);
+
+		assert_eq!(res, Err(InvalidBackReference { req: 1, idx: 0 }));
+		assert_eq!(builder.build().requests().len(), 2);
+	}
+
+	#[test]
+	#[should_panic]
+	fn extend_bad_backref() {
+		let mut builder = RequestBuilder::default();
+		builder.extend(vec![
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+		]);
+	}
+
+	#[test]
+	fn deadlines() {
+		use std::time::{Duration, Instant};
+
+		let mut requests = proof_and_receipts().build();
+		let now = Instant::now();
+		assert!(!requests.expired(now));
+
+		requests.mark_sent(now + Duration::from_secs(5));
+		assert!(!requests.expired(now));
+		assert!(requests.expired(now + Duration::from_secs(5)));
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		})).unwrap();
+
+		// deadlines apply only to the request they were set for.
+		assert!(!requests.expired(now + Duration::from_secs(5)));
+	}
+
+	#[test]
+	fn into_complete() {
+		let mut requests = proof_and_receipts().build();
+		assert!(requests.clone().into_complete().is_none());
+
+		requests.supply_responses(&(), &[
+			Response::HeaderProof(HeaderProofResponse {
+				proof: vec![],
+				hash: 5.into(),
+				td: 100.into(),
+			}),
+			Response::Receipts(ReceiptsResponse { receipts: vec![] }),
+		]).unwrap();
+
+		assert_eq!(requests.into_complete(), Some(vec![
+			CompleteRequest::HeaderProof(CompleteHeaderProofRequest { num: 100 }),
+			CompleteRequest::Receipts(CompleteReceiptsRequest { hash: 5.into() }),
+		]));
+	}
+
+	#[test]
+	fn respond_to_all_reports_progress() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: H256::default().into(),
+		})).unwrap();
+
+		let mut progress = Vec::new();
+		let responses = builder.build().respond_to_all_with(|req| match req {
+			CompleteRequest::HeaderProof(_) => Some(Response::HeaderProof(HeaderProofResponse {
+				proof: vec![],
+				hash: H256::from(1),
+				td: 100.into(),
+			})),
+			CompleteRequest::Receipts(ref req) if req.hash == H256::default() => None,
+			_ => Some(Response::Receipts(ReceiptsResponse { receipts: vec![] })),
+		}, |answered, total| progress.push((answered, total)));
+
+		// the responder stops at the third request.
+		assert_eq!(responses.len(), 2);
+		assert_eq!(progress, vec![(1, 3), (2, 3)]);
+	}
+
+	#[test]
+	fn next_dependencies() {
+		let builder = proof_and_receipts();
+
+		// the first request has no back-references.
+		let mut requests = builder.build();
+		assert!(requests.next_dependencies().is_empty());
+
+		// the second request depends on the output of the first one, which is known now.
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		})).unwrap();
+		assert!(requests.next_dependencies().is_empty());
+		assert!(requests.next_complete().is_some());
+	}
+
+	#[test]
+	fn next_dependencies_of_unfilled_request() {
+		let mut builder = RequestBuilder::default();
+		builder.push(HashRequest(Field::Scalar(H256::from(1)))).unwrap();
+		builder.push(HashRequest(Field::BackReference(0, 0))).unwrap();
+
+		// the first response withholds the hash the second request depends on.
+		let mut requests = builder.build();
+		requests.supply_response(&(), &HashResponse(None)).unwrap();
+		assert_eq!(requests.next_dependencies(), vec![(0, 0)]);
+
+		requests.prefill_output((0, 0), Output::Hash(H256::from(2))).unwrap();
+		assert!(requests.next_dependencies().is_empty());
+		assert_eq!(requests.next_complete(), Some(H256::from(2)));
+	}
+
+	#[test]
+	fn push_within_budget() {
+		use net::request_credits::CostTable;
+
+		let table = CostTable::default();
+		let mut remaining: U256 = 20000.into();
+		let mut builder = RequestBuilder::default();
+
+		// header proof of 15000 fits.
+		builder.push_within_budget(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		}), &table, &mut remaining).unwrap();
+		assert_eq!(remaining, 5000.into());
+
+		// another header proof does not.
+		let res = builder.push_within_budget(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 101.into(),
+		}), &table, &mut remaining);
+		assert_eq!(res, Err(PushError::OverBudget { cost: 15000.into(), remaining: 5000.into() }));
+		assert_eq!(remaining, 5000.into());
+
+		// a bad back-reference leaves the budget untouched.
+		let res = builder.push_within_budget(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(1, 0),
+		}), &table, &mut remaining);
+		assert_eq!(res, Err(PushError::BadReference(InvalidBackReference { req: 1, idx: 0 })));
+		assert_eq!(remaining, 5000.into());
+
+		// receipts of 5000 exactly exhaust the budget.
+		builder.push_within_budget(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		}), &table, &mut remaining).unwrap();
+		assert_eq!(remaining, U256::zero());
+		assert_eq!(builder.build().requests().len(), 2);
+	}
+
+	#[test]
+	fn into_builder_keeps_pending_tail() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(2, 0),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		})).unwrap();
+
+		// the reference to the answered request is filled, the other one shifted.
+		let mut builder = requests.into_builder();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(1, 0),
+		})).unwrap();
+
+		let requests = builder.build();
+		assert_eq!(requests.requests(), &[
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: H256::from(1).into(),
+			}),
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 200.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(1, 0),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(1, 0),
+			}),
+		][..]);
+	}
+
+	#[test]
+	fn into_builder_drops_requests_depending_on_skipped() {
+		fn always(_: &HashMap<(usize, usize), Output>) -> bool { true }
+
+		let mut builder = RequestBuilder::default();
+		builder.push_optional(Optional::new(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		}), always)).unwrap();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		})).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(1, 0),
+		})).unwrap();
+
+		let requests = builder.build();
+		assert!(requests.is_skipped(0));
+		assert_eq!(requests.num_answered(), 1);
+
+		let builder = requests.into_builder();
+		assert_eq!(builder.build().requests(), &[
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 200.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+		][..]);
+	}
+
+	#[test]
+	fn same_chain_ignores_progress() {
+		let builder = proof_and_receipts();
+
+		let fresh = builder.clone().build();
+		let mut progressed = builder.build();
+		progressed.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		})).unwrap();
+
+		assert!(fresh != progressed);
+		assert!(fresh.same_chain(&progressed));
+		assert!(progressed.same_chain(&fresh));
+
+		let mut other = RequestBuilder::default();
+		other.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 101.into(),
+		})).unwrap();
+		assert!(!fresh.same_chain(&other.build()));
+	}
+
+	#[test]
+	fn output_kind_at() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+
+		assert_eq!(builder.output_kind_at(0, 0), Some(OutputKind::Hash));
+		assert_eq!(builder.output_kind_at(0, 5), None);
+		assert_eq!(builder.output_kind_at(1, 0), None);
+	}
+
+	#[test]
+	fn can_reference_wrong_kind() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+
+		// header proof puts a hash at output 0, which a number field can't consume.
+		assert!(!builder.can_reference(Field::<u64>::expected_kind(), 0, 0));
+		assert!(builder.can_reference(Field::<H256>::expected_kind(), 0, 0));
+		assert!(!builder.can_reference(Field::<H256>::expected_kind(), 0, 1));
+		assert!(!builder.can_reference(Field::<H256>::expected_kind(), 1, 0));
+	}
+
+	#[test]
+	fn map_requests_indexed() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		})).unwrap();
+
+		let mut indices = Vec::new();
+		let mapped = requests.map_requests_indexed(|i, req| { indices.push(i); req });
+
+		assert_eq!(indices, vec![0, 1, 2]);
+		assert_eq!(mapped.num_answered(), 1);
+		assert!(mapped.next_dependencies().is_empty());
+	}
+
+	#[test]
+	fn collected_outputs() {
+		let mut requests = proof_and_receipts().build();
+		assert!(requests.collected_outputs().is_empty());
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		})).unwrap();
+
+		assert_eq!(requests.collected_outputs().len(), 1);
+		assert_eq!(requests.collected_outputs().get(&(0, 0)), Some(&Output::Hash(H256::from(1))));
+	}
+
+	#[test]
+	fn supply_responses_out_of_order() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+
+		let mut requests = builder.build();
+
+		// later responses are buffered.
+		let res = requests.supply_response_at(2, &(), Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(2),
+			td: 200.into(),
+		}));
+		assert_eq!(res, Ok(vec![]));
+		let res = requests.supply_response_at(1, &(), Response::Receipts(ReceiptsResponse {
+			receipts: vec![],
+		}));
+		assert_eq!(res, Ok(vec![]));
+		assert_eq!(requests.num_answered(), 0);
+		assert_eq!(requests.supply_response_at(3, &(), Response::Receipts(ReceiptsResponse {
+			receipts: vec![],
+		})), Err((vec![], ResponseError::Unexpected.at(3))));
+
+		// a second response for a buffered request is rejected.
+		assert_eq!(requests.supply_response_at(1, &(), Response::Receipts(ReceiptsResponse {
+			receipts: vec![],
+		})), Err((vec![], ResponseError::Unexpected.at(1))));
+
+		// the first one releases the rest, filling back-references in order.
+		let res = requests.supply_response_at(0, &(), Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		}));
+		assert_eq!(res.map(|extracted| extracted.len()), Ok(3));
+		assert!(requests.is_complete());
+		assert_eq!(requests.requests()[1], Request::Receipts(IncompleteReceiptsRequest {
+			hash: H256::from(1).into(),
+		}));
+		assert_eq!(requests.collected_outputs().get(&(2, 0)), Some(&Output::Hash(H256::from(2))));
+		assert_eq!(requests.supply_response_at(0, &(), Response::Receipts(ReceiptsResponse {
+			receipts: vec![],
+		})), Err((vec![], ResponseError::Unexpected.at(0))));
+	}
+
+	#[test]
+	fn bad_buffered_response_is_reported() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 200.into(),
+		})).unwrap();
+
+		let header_proof = || Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		});
+
+		let mut requests = builder.build();
+		assert_eq!(requests.supply_response_at(1, &(), header_proof()), Ok(vec![]));
+		assert_eq!(requests.supply_response_at(2, &(), header_proof()), Ok(vec![]));
+
+		// the data extracted from the response applied before the bad one is kept.
+		let res = requests.supply_response_at(0, &(), header_proof());
+		assert_eq!(res.map_err(|(extracted, e)| (extracted.len(), e)), Err((1, ResponseError::Validity(WrongKind).at(1))));
+		assert_eq!(requests.num_answered(), 1);
+
+		// the response buffered after the bad one is still applied.
+		let res = requests.supply_response_at(1, &(), Response::Receipts(ReceiptsResponse { receipts: vec![] }));
+		assert_eq!(res.map(|extracted| extracted.len()), Ok(2));
+		assert!(requests.is_complete());
+	}
+
+	#[test]
+	fn buffered_responses_of_skipped_requests_are_dropped() {
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		})).unwrap();
+		builder.push_optional(Optional::new(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		}), no_transactions)).unwrap();
+
+		let header_proof = Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		});
+
+		let mut expected = builder.clone().build();
+		expected.supply_response(&(), &header_proof).unwrap();
+
+		let mut requests = builder.build();
+		requests.supply_response_at(1, &(), Response::Receipts(ReceiptsResponse { receipts: vec![] })).unwrap();
+		assert_eq!(requests.supply_response_at(0, &(), header_proof).map(|extracted| extracted.len()), Ok(1));
+		assert!(requests.is_skipped(1));
+		assert_eq!(requests, expected);
+	}
+
+	#[test]
+	fn respond_to_all_controlled_resumes_after_decline() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::Scalar(H256::default()),
+		})).unwrap();
+
+		let header_proof = || Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		});
+		let receipts = || Response::Receipts(ReceiptsResponse { receipts: vec![] });
+
+		// declining the first request also declines the second, which depends on it.
+		let (responses, reason) = builder.clone().build().respond_to_all_controlled(|req| match req {
+			CompleteRequest::HeaderProof(_) => Respond::Decline,
+			_ => Respond::Answer(receipts()),
+		});
+		assert_eq!(responses, vec![receipts()]);
+		assert_eq!(reason, Completion::Complete);
+
+		// declining a request in the middle resumes with the following one.
+		let (responses, reason) = builder.clone().build().respond_to_all_controlled(|req| match req {
+			CompleteRequest::HeaderProof(_) => Respond::Answer(header_proof()),
+			CompleteRequest::Receipts(ref req) if req.hash == H256::from(1) => Respond::Decline,
+			_ => Respond::Answer(receipts()),
+		});
+		assert_eq!(responses, vec![header_proof(), receipts()]);
+		assert_eq!(reason, Completion::Complete);
+
+		let (responses, reason) = builder.build().respond_to_all_controlled(|req| match req {
+			CompleteRequest::HeaderProof(_) => Respond::Decline,
+			_ => Respond::Halt,
+		});
+		assert!(responses.is_empty());
+		assert_eq!(reason, Completion::ResponderStopped);
+	}
+
+	#[test]
+	fn declined_requests_are_skipped() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::Scalar(H256::default()),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		requests.skip_next();
+		assert!(requests.is_skipped(0));
+		assert!(requests.is_skipped(1));
+		assert_eq!(requests.num_answered(), 2);
+		assert_eq!(requests.next_complete(), Some(CompleteRequest::Receipts(CompleteReceiptsRequest {
+			hash: H256::default(),
+		})));
+
+		requests.supply_response(&(), &Response::Receipts(ReceiptsResponse { receipts: vec![] })).unwrap();
+		assert_eq!(requests.into_complete().map(|complete| complete.len()), Some(1));
+	}
+
+	#[test]
+	fn summary_counts_kinds() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::Scalar(H256::default()),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		let summary = requests.summary();
+		assert_eq!(summary.total, 3);
+		assert_eq!(summary.answered, 0);
+		assert_eq!(summary.pending, 3);
+		assert_eq!(summary.kinds.len(), 2);
+		assert_eq!(summary.kinds["header_proof"], 1);
+		assert_eq!(summary.kinds["receipts"], 2);
+
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		})).unwrap();
+
+		let summary = requests.summary();
+		assert_eq!(summary.total, 3);
+		assert_eq!(summary.answered, 1);
+		assert_eq!(summary.pending, 2);
+		assert_eq!(summary.kinds["receipts"], 2);
+	}
+
+	#[test]
+	fn fill_trace_records_fills() {
+		let builder = proof_and_receipts();
+
+		let response = Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		});
+
+		let mut untraced = builder.clone().build();
+		untraced.supply_response(&(), &response).unwrap();
+		assert!(untraced.fill_trace().is_empty());
+
+		let mut requests = builder.build();
+		requests.set_fill_trace(true);
+		requests.supply_response(&(), &response).unwrap();
+		assert_eq!(requests.fill_trace(), &[FillEvent {
+			consuming_req: 1,
+			field_idx: 0,
+			source: (0, 0),
+			found: true,
+		}]);
+
+		requests.set_fill_trace(false);
+		assert!(requests.fill_trace().is_empty());
+	}
+
+	#[test]
+	fn cancel_remaining() {
+		let mut builder = proof_and_receipts();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		requests.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		})).unwrap();
+
+		requests.cancel_remaining();
+		assert!(requests.is_complete());
+		assert!(requests.next_complete().is_none());
+		assert_eq!(requests.num_pending(), 0);
+		assert_eq!(requests.collected_outputs().get(&(0, 0)), Some(&Output::Hash(H256::default())));
+
+		let receipts = Response::Receipts(ReceiptsResponse { receipts: vec![] });
+		assert_eq!(requests.supply_response(&(), &receipts), Err(ResponseError::Unexpected.at(3)));
+		assert_eq!(requests.supply_response_at(2, &(), receipts), Err((vec![], ResponseError::Unexpected.at(2))));
+
+		// only the answered request is complete.
+		assert!(!requests.is_skipped(0));
+		assert!(requests.is_skipped(1) && requests.is_skipped(2));
+		assert_eq!(requests.into_complete(), Some(vec![
+			CompleteRequest::HeaderProof(CompleteHeaderProofRequest { num: 100 }),
+		]));
+
+		// cancelled requests are omitted even if they could have been completed.
+		let mut builder = RequestBuilder::default();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: H256::default().into(),
+		})).unwrap();
+
+		let mut requests = builder.build();
+		requests.cancel_remaining();
+		assert!(requests.is_skipped(0));
+		assert_eq!(requests.into_complete(), Some(vec![]));
+	}
+
+	#[test]
+	fn fill_from_shared_cache() {
+		let build = || proof_and_receipts().build();
+
+		let mut first = build();
+		first.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(5),
+			td: 100.into(),
+		})).unwrap();
+
+		let mut cache = OutputCache::new();
+		first.export_outputs(&mut cache);
+		assert_eq!(cache.len(), 1);
+
+		let mut second = build();
+		assert!(second.requests()[1].clone().complete().is_err());
+
+		second.fill_from(&cache);
+		assert_eq!(second.requests()[1].clone().complete().unwrap(), CompleteRequest::Receipts(CompleteReceiptsRequest {
+			hash: H256::from(5),
+		}));
+		assert_eq!(second.collected_outputs().get(&(0, 0)), Some(&Output::Hash(H256::from(5))));
+		assert_eq!(second.num_answered(), 0);
+	}
+
+	#[test]
+	fn fill_from_skips_optional() {
+		let header_proof = || Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		});
+
+		let mut builder = RequestBuilder::default();
+		builder.push(header_proof()).unwrap();
+		let mut first = builder.build();
+		first.supply_response(&(), &Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::from(1),
+			td: 100.into(),
+		})).unwrap();
+
+		let mut cache = OutputCache::new();
+		first.export_outputs(&mut cache);
+
+		let mut builder = RequestBuilder::default();
+		builder.push_optional(Optional::new(header_proof(), no_transactions)).unwrap();
+		let mut second = builder.build();
+		assert!(!second.is_complete());
+
+		// the cached hash satisfies the condition of the optional request.
+		second.fill_from(&cache);
+		assert!(second.is_skipped(0));
+		assert!(second.is_complete());
+	}
+
+	#[test]
+	fn stream_matches_builder() {
+		let requests = vec![
+			Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}),
+			Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::Scalar(H256::default()),
+			}),
+		];
+
+		let mut builder = RequestBuilder::default();
+		for req in requests.clone() {
+			builder.push(req).unwrap();
+		}
+
+		let mut stream = RequestStream::new(requests.clone());
+		let streamed: Vec<_> = stream.by_ref().collect::<Result<_, _>>().unwrap();
+		assert_eq!(stream.output_kinds(), builder.output_kinds());
+		assert_eq!(&streamed[..], builder.clone().build().requests());
+		assert_eq!(stream.num_yielded(), 3);
+
+		// streaming stops at the first bad back-reference.
+		let bad = Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(2, 0),
+		});
+		let mut stream = RequestStream::new(vec![requests[0].clone(), bad.clone(), requests[1].clone()]);
+		assert_eq!(stream.next(), Some(Ok(requests[0].clone())));
+		assert_eq!(builder.clone().push(bad), Err(InvalidBackReference { req: 2, idx: 0 }));
+		assert_eq!(stream.next(), Some(Err(InvalidBackReference { req: 2, idx: 0 })));
+		assert_eq!(stream.next(), None);
+	}
+
+	#[test]
+	fn response_error_carries_request_index() {
+		let mut requests = proof_and_receipts().build();
+		let proof = Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: H256::default(),
+			td: 100.into(),
+		});
+		requests.supply_response(&(), &proof).unwrap();
+
+		let err = requests.supply_response(&(), &proof).unwrap_err();
+		assert_eq!(err.index, 1);
+		assert_eq!(err.error, ResponseError::Validity(WrongKind));
+		assert_eq!(err.to_string(), "request 1: invalid response: WrongKind");
+	}
+
+	#[test]
+	fn respond_to_all_limited() {
+		let mut builder = RequestBuilder::default();
+		for _ in 0..5 {
+			builder.push(Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::Scalar(H256::default()),
+			})).unwrap();
+		}
+		let requests = builder.build();
+
+		let responder = |_| Some(Response::Receipts(ReceiptsResponse { receipts: vec![] }));
+		let max = 2;
+		let responses = requests.clone().respond_to_all_limited(&responder, max);
+		assert_eq!(responses.len(), max);
+
+		// supplying the limited responses leaves the rest of the batch pending.
+		let mut partial = requests.clone();
+		partial.supply_responses(&(), &responses).unwrap();
+		assert!(!partial.is_complete());
+		assert_eq!(partial.num_answered(), max);
+		assert_eq!(partial.num_pending(), 3);
+
+		assert_eq!(requests.clone().respond_to_all_limited(&responder, 0).len(), 0);
+		assert_eq!(requests.respond_to_all_limited(&responder, 10).len(), 5);
+	}
+
+	#[test]
+	fn priorities() {
+		let mut builder = RequestBuilder::default();
+		for _ in 0..3 {
+			builder.push(Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::Scalar(H256::default()),
+			})).unwrap();
+		}
+		let mut requests = builder.build();
+		assert_eq!(requests.priority(1), 0);
+
+		requests.set_priority(1, 7);
+		requests.set_priority(2, 3);
+		assert_eq!(requests.priority(0), 0);
+		assert_eq!(requests.priority(1), 7);
+		assert_eq!(requests.priority(2), 3);
+		assert_eq!(requests.priority(5), 0);
+
+		let requests = requests.map_requests(|req| req);
+		assert_eq!(requests.priority(1), 7);
+	}
+
+	#[test]
+	fn empty_chain() {
+		let requests = RequestBuilder::<Request>::default().build();
+		assert!(requests.is_complete());
+		assert!(requests.next_complete().is_none());
+		assert_eq!(requests.progress(), 1.0);
+		assert_eq!(requests.num_pending(), 0);
+		assert_eq!(requests.summary().total, 0);
+
+		let responder = |_| -> Option<Response> { panic!("no requests to respond to") };
+		assert!(requests.clone().respond_to_all(&responder).is_empty());
+		assert_eq!(requests.clone().respond_to_all_with_reason(&responder), (vec![], Completion::Complete));
+		assert!(requests.clone().respond_to_all_limited(&responder, 0).is_empty());
+		assert_eq!(requests.into_complete(), Some(vec![]));
+	}
+
+	#[test]
+	fn optional_request_is_skipped() {
+		let build = || {
+			let mut builder = RequestBuilder::default();
+			builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			})).unwrap();
+			builder.push_optional(Optional::new(Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			}), no_transactions)).unwrap();
+			builder.push(Request::Body(IncompleteBodyRequest {
+				hash: Field::BackReference(0, 0),
+			})).unwrap();
+			builder.build()
+		};
+		let proof = |hash: H256| Response::HeaderProof(HeaderProofResponse {
+			proof: vec![],
+			hash: hash,
+			td: 100.into(),
+		});
+
+		let mut requests = build();
+		assert_eq!(requests.num_answered(), 0);
+		requests.supply_response(&(), &proof(H256::from(1))).unwrap();
+		assert_eq!(requests.num_answered(), 2);
+		assert!(requests.is_skipped(1));
+		assert_eq!(requests.next_complete(), Some(CompleteRequest::Body(CompleteBodyRequest {
+			hash: H256::from(1),
+		})));
+
+		let mut requests = build();
+		requests.supply_response(&(), &proof(H256::from(2))).unwrap();
+		assert_eq!(requests.num_answered(), 1);
+		assert!(!requests.is_skipped(1));
+		assert_eq!(requests.next_complete(), Some(CompleteRequest::Receipts(CompleteReceiptsRequest {
+			hash: H256::from(2),
+		})));
+	}
+
+	#[test]
+	fn requests_depending_on_skipped_are_skipped() {
+		fn always(_: &HashMap<(usize, usize), Output>) -> bool { true }
+
+		let mut builder = RequestBuilder::default();
+		builder.push_optional(Optional::new(Request::HeaderProof(IncompleteHeaderProofRequest {
+			num: 100.into(),
+		}), always)).unwrap();
+		builder.push(Request::Receipts(IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		})).unwrap();
+
+		let requests = builder.build();
+		assert!(requests.is_complete());
+		assert!(requests.is_skipped(0));
+		assert!(requests.is_skipped(1));
+		assert_eq!(requests.into_complete(), Some(vec![]));
+	}
+
+	#[test]
+	fn equality_only_considers_which_requests_are_optional() {
+		fn always(_: &HashMap<(usize, usize), Output>) -> bool { true }
+
+		let builder = |skip_if: Option<SkipPredicate>| {
+			let mut builder = RequestBuilder::default();
+			builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: 100.into(),
+			})).unwrap();
+			let receipts = Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(0, 0),
+			});
+			match skip_if {
+				Some(skip_if) => builder.push_optional(Optional::new(receipts, skip_if)).unwrap(),
+				None => builder.push(receipts).unwrap(),
+			}
+			builder
+		};
+
+		let (always, no_transactions) = (always as SkipPredicate, no_transactions as SkipPredicate);
+		assert_eq!(builder(Some(always)), builder(Some(no_transactions)));
+		assert_eq!(builder(Some(always)).build(), builder(Some(no_transactions)).build());
+		assert!(builder(Some(always)) != builder(None));
+		assert!(builder(Some(always)).build() != builder(None).build());
+	}
+
+	#[test]
+	fn last_output_of_kind() {
+		let mut builder = RequestBuilder::default();
+		assert_eq!(builder.last_output_of_kind(OutputKind::Hash), None);
+
+		for num in 0..2 {
+			builder.push(Request::HeaderProof(IncompleteHeaderProofRequest {
+				num: num.into(),
+			})).unwrap();
+			builder.push(Request::Receipts(IncompleteReceiptsRequest {
+				hash: Field::BackReference(num as usize * 2, 0),
+			})).unwrap();
+		}
+
+		assert_eq!(builder.last_output_of_kind(OutputKind::Hash), Some((2, 0)));
+		assert_eq!(builder.last_output_of_kind(OutputKind::Number), None);
+	}
 }
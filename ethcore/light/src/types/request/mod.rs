@@ -16,6 +16,7 @@
 
 //! Light protocol request types.
 
+use std::fmt;
 use rlp::{Encodable, Decodable, DecoderError, RlpStream, UntrustedRlp};
 use util::H256;
 
@@ -63,12 +64,29 @@ pub use self::execution::{
 	Response as ExecutionResponse,
 };
 
-pub use self::builder::{RequestBuilder, Requests};
+pub use self::builder::{RequestBuilder, RequestStream, Requests, BatchSummary, Completion, FillEvent, OutputCache, OutputRef, Respond, PushError,
+	Optional, SkipPredicate};
 
 /// Error indicating a reference to a non-existent or wrongly-typed output.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NoSuchOutput;
 
+/// Error indicating a back-reference to a non-existent or wrongly-typed output,
+/// along with the coordinates of the output referenced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidBackReference {
+	/// Index of the referenced request.
+	pub req: usize,
+	/// Index of the referenced output.
+	pub idx: usize,
+}
+
+impl From<InvalidBackReference> for NoSuchOutput {
+	fn from(_: InvalidBackReference) -> Self {
+		NoSuchOutput
+	}
+}
+
 /// Wrong kind of response corresponding to request.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct WrongKind;
@@ -82,6 +100,41 @@ pub enum ResponseError<T> {
 	Unexpected,
 }
 
+impl<T> ResponseError<T> {
+	/// Attach the index of the request within the chain the response was supplied for.
+	pub fn at(self, index: usize) -> IndexedResponseError<T> {
+		IndexedResponseError {
+			index: index,
+			error: self,
+		}
+	}
+}
+
+impl<T: fmt::Debug> fmt::Display for ResponseError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ResponseError::Validity(ref err) => write!(f, "invalid response: {:?}", err),
+			ResponseError::Unexpected => write!(f, "unexpected response"),
+		}
+	}
+}
+
+/// Error on processing a response, along with the index of the request
+/// within the chain it was supplied for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedResponseError<T> {
+	/// Index of the request.
+	pub index: usize,
+	/// The error.
+	pub error: ResponseError<T>,
+}
+
+impl<T: fmt::Debug> fmt::Display for IndexedResponseError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "request {}: {}", self.index, self.error)
+	}
+}
+
 /// An input to a request.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Field<T> {
@@ -106,6 +159,34 @@ impl<T> Field<T> {
 			*req_idx = mapping(*req_idx)
 		}
 	}
+
+	fn back_reference(&self) -> Option<(usize, usize)> {
+		match *self {
+			Field::Scalar(_) => None,
+			Field::BackReference(req, idx) => Some((req, idx)),
+		}
+	}
+
+	// revert to the given back-reference, if any.
+	fn restore(&mut self, back_ref: Option<(usize, usize)>) {
+		if let Some((req, idx)) = back_ref {
+			*self = Field::BackReference(req, idx);
+		}
+	}
+}
+
+impl Field<H256> {
+	/// The kind of output a back-reference in a hash field must point to.
+	pub fn expected_kind() -> OutputKind {
+		OutputKind::Hash
+	}
+}
+
+impl Field<u64> {
+	/// The kind of output a back-reference in a number field must point to.
+	pub fn expected_kind() -> OutputKind {
+		OutputKind::Number
+	}
 }
 
 impl<T> From<T> for Field<T> {
@@ -161,7 +242,7 @@ impl Output {
 }
 
 /// Response output kinds which can be used as back-references.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum OutputKind {
 	/// A 32-byte hash output.
 	Hash,
@@ -377,6 +458,32 @@ impl IncompleteRequest for Request {
 			Request::Execution(ref mut req) => req.adjust_refs(mapping),
 		}
 	}
+
+	fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+		match *self {
+			Request::Headers(ref req) => req.back_references(),
+			Request::HeaderProof(ref req) => req.back_references(),
+			Request::Receipts(ref req) => req.back_references(),
+			Request::Body(ref req) => req.back_references(),
+			Request::Account(ref req) => req.back_references(),
+			Request::Storage(ref req) => req.back_references(),
+			Request::Code(ref req) => req.back_references(),
+			Request::Execution(ref req) => req.back_references(),
+		}
+	}
+
+	fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+		match *self {
+			Request::Headers(ref mut req) => req.restore_back_references(refs),
+			Request::HeaderProof(ref mut req) => req.restore_back_references(refs),
+			Request::Receipts(ref mut req) => req.restore_back_references(refs),
+			Request::Body(ref mut req) => req.restore_back_references(refs),
+			Request::Account(ref mut req) => req.restore_back_references(refs),
+			Request::Storage(ref mut req) => req.restore_back_references(refs),
+			Request::Code(ref mut req) => req.restore_back_references(refs),
+			Request::Execution(ref mut req) => req.restore_back_references(refs),
+		}
+	}
 }
 
 impl CheckedRequest for Request {
@@ -434,6 +541,22 @@ impl Decodable for Kind {
 	}
 }
 
+impl Kind {
+	/// Stable, human-readable name of the request kind.
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Kind::Headers => "headers",
+			Kind::HeaderProof => "header_proof",
+			Kind::Receipts => "receipts",
+			Kind::Body => "body",
+			Kind::Account => "account",
+			Kind::Storage => "storage",
+			Kind::Code => "code",
+			Kind::Execution => "execution",
+		}
+	}
+}
+
 impl Encodable for Kind {
 	fn rlp_append(&self, s: &mut RlpStream) {
 		s.append(&(*self as u8));
@@ -559,6 +682,16 @@ pub trait IncompleteRequest: Sized {
 
 	/// Adjust back-reference request indices.
 	fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize;
+
+	/// Get the back-reference held by each field which may hold one,
+	/// in declaration order.
+	fn back_references(&self) -> Vec<Option<(usize, usize)>>;
+
+	/// Restore back-references as yielded by `back_references`, undoing any
+	/// fills of those fields. Fields without a back-reference are left as they are.
+	///
+	/// Panics if `refs` is shorter than what `back_references` yields.
+	fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]);
 }
 
 /// A request which can be checked against its response for more validity.
@@ -660,6 +793,14 @@ pub mod header {
 		fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
 			self.start.adjust_req(mapping)
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.start.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.start.restore(refs[0]);
+		}
 	}
 
 	/// A complete header request.
@@ -778,6 +919,14 @@ pub mod header_proof {
 		fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
 			self.num.adjust_req(mapping)
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.num.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.num.restore(refs[0]);
+		}
 	}
 
 	/// A complete header proof request.
@@ -871,7 +1020,7 @@ pub mod block_receipts {
 		fn fill<F>(&mut self, oracle: F) where F: Fn(usize, usize) -> Result<Output, NoSuchOutput> {
 			if let Field::BackReference(req, idx) = self.hash {
 				self.hash = match oracle(req, idx) {
-					Ok(Output::Number(hash)) => Field::Scalar(hash.into()),
+					Ok(Output::Hash(hash)) => Field::Scalar(hash.into()),
 					_ => Field::BackReference(req, idx),
 				}
 			}
@@ -886,6 +1035,14 @@ pub mod block_receipts {
 		fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
 			self.hash.adjust_req(mapping)
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.hash.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.hash.restore(refs[0]);
+		}
 	}
 
 	/// A complete block receipts request.
@@ -983,6 +1140,14 @@ pub mod block_body {
 		fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
 			self.hash.adjust_req(mapping)
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.hash.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.hash.restore(refs[0]);
+		}
 	}
 
 	/// A complete block body request.
@@ -1108,6 +1273,15 @@ pub mod account {
 			self.block_hash.adjust_req(&mut mapping);
 			self.address_hash.adjust_req(&mut mapping);
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.block_hash.back_reference(), self.address_hash.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.block_hash.restore(refs[0]);
+			self.address_hash.restore(refs[1]);
+		}
 	}
 
 	/// A complete request for an account.
@@ -1264,6 +1438,16 @@ pub mod storage {
 			self.address_hash.adjust_req(&mut mapping);
 			self.key_hash.adjust_req(&mut mapping);
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.block_hash.back_reference(), self.address_hash.back_reference(), self.key_hash.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.block_hash.restore(refs[0]);
+			self.address_hash.restore(refs[1]);
+			self.key_hash.restore(refs[2]);
+		}
 	}
 
 	/// A complete request for a storage proof.
@@ -1389,6 +1573,15 @@ pub mod contract_code {
 			self.block_hash.adjust_req(&mut mapping);
 			self.code_hash.adjust_req(&mut mapping);
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.block_hash.back_reference(), self.code_hash.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.block_hash.restore(refs[0]);
+			self.code_hash.restore(refs[1]);
+		}
 	}
 
 	/// A complete request.
@@ -1525,6 +1718,14 @@ pub mod execution {
 		fn adjust_refs<F>(&mut self, mapping: F) where F: FnMut(usize) -> usize {
 			self.block_hash.adjust_req(mapping);
 		}
+
+		fn back_references(&self) -> Vec<Option<(usize, usize)>> {
+			vec![self.block_hash.back_reference()]
+		}
+
+		fn restore_back_references(&mut self, refs: &[Option<(usize, usize)>]) {
+			self.block_hash.restore(refs[0]);
+		}
 	}
 
 	/// A complete request.
@@ -1852,4 +2053,18 @@ mod tests {
 		let raw = ::rlp::encode_list(&reqs);
 		assert_eq!(::rlp::decode_list::<Response>(&raw), reqs);
 	}
+
+	#[test]
+	fn receipts_filled_from_hash_output() {
+		let mut req = IncompleteReceiptsRequest {
+			hash: Field::BackReference(0, 0),
+		};
+
+		// number outputs don't fill the hash.
+		req.fill(|_, _| Ok(Output::Number(100)));
+		assert_eq!(req.hash, Field::BackReference(0, 0));
+
+		req.fill(|_, _| Ok(Output::Hash(H256::from(5))));
+		assert_eq!(req.hash, Field::Scalar(H256::from(5)));
+	}
 }
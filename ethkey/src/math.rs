@@ -57,6 +57,24 @@ pub fn public_negate(public: &mut Public) -> Result<(), Error> {
 	Ok(())
 }
 
+/// Serialize public key in compressed form (33 bytes)
+pub fn public_to_compressed(public: &Public) -> Result<Vec<u8>, Error> {
+	let key_public = to_secp256k1_public(public)?;
+	Ok(key_public.serialize_vec(&SECP256K1, true).to_vec())
+}
+
+/// Deserialize public key from compressed form (33 bytes)
+pub fn public_from_compressed(data: &[u8]) -> Result<Public, Error> {
+	if data.len() != 33 {
+		return Err(Error::InvalidPublic);
+	}
+
+	let key_public = key::PublicKey::from_slice(&SECP256K1, data)?;
+	let mut public = Public::default();
+	set_public(&mut public, &key_public);
+	Ok(public)
+}
+
 /// Return base point of secp256k1
 pub fn generation_point() -> Public {
 	let mut public_sec_raw = [0u8; 65];
@@ -94,7 +112,7 @@ fn set_public(public: &mut Public, key_public: &key::PublicKey) {
 #[cfg(test)]
 mod tests {
 	use super::super::{Random, Generator};
-	use super::{public_add, public_sub};
+	use super::{public_add, public_sub, public_to_compressed, public_from_compressed};
 
 	#[test]
 	fn public_addition_is_commutative() {
@@ -121,4 +139,12 @@ mod tests {
 
 		assert_eq!(sum, public1);
 	}
+
+	#[test]
+	fn public_compression_is_reversible() {
+		let public = Random.generate().unwrap().public().clone();
+		let compressed = public_to_compressed(&public).unwrap();
+		assert_eq!(compressed.len(), 33);
+		assert_eq!(public_from_compressed(&compressed).unwrap(), public);
+	}
 }
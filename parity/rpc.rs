@@ -110,7 +110,7 @@ impl rpc::HttpMetaExtractor for RpcExtractor {
 
 		metadata.origin = match (origin.as_str(), dapps_origin) {
 			("null", Some(dapp)) => Origin::Dapps(dapp.into()),
-			_ => Origin::Rpc(origin),
+			_ => Origin::Rpc(origin.into()),
 		};
 
 		metadata
@@ -35,7 +35,7 @@ impl Metadata {
 	pub fn dapp_id(&self) -> DappId {
 		// TODO [ToDr] Extract dapp info from Ws connections.
 		match self.origin {
-			Origin::Dapps(ref dapp_id) => dapp_id.clone(),
+			Origin::Dapps(ref origin) => origin.id.clone(),
 			_ => DappId::default(),
 		}
 	}
@@ -95,7 +95,7 @@ fn should_return_list_of_items_to_confirm() {
 		nonce: None,
 		condition: None,
 	}), Origin::Dapps("http://parity.io".into())).unwrap();
-	tester.signer.add_request(ConfirmationPayload::EthSignMessage(1.into(), vec![5].into()), Origin::Unknown).unwrap();
+	tester.signer.add_request(ConfirmationPayload::EthSignMessage(1.into(), vec![5].into()), Origin::Unknown(None)).unwrap();
 
 	// when
 	let request = r#"{"jsonrpc":"2.0","method":"signer_requestsToConfirm","params":[],"id":1}"#;
@@ -125,7 +125,7 @@ fn should_reject_transaction_from_queue_without_dispatching() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 	assert_eq!(tester.signer.requests().len(), 1);
 
 	// when
@@ -152,7 +152,7 @@ fn should_not_remove_transaction_if_password_is_invalid() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 	assert_eq!(tester.signer.requests().len(), 1);
 
 	// when
@@ -168,7 +168,7 @@ fn should_not_remove_transaction_if_password_is_invalid() {
 fn should_not_remove_sign_if_password_is_invalid() {
 	// given
 	let tester = signer_tester();
-	tester.signer.add_request(ConfirmationPayload::EthSignMessage(0.into(), vec![5].into()), Origin::Unknown).unwrap();
+	tester.signer.add_request(ConfirmationPayload::EthSignMessage(0.into(), vec![5].into()), Origin::Unknown(None)).unwrap();
 	assert_eq!(tester.signer.requests().len(), 1);
 
 	// when
@@ -196,7 +196,7 @@ fn should_confirm_transaction_and_dispatch() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 
 	let t = Transaction {
 		nonce: U256::zero(),
@@ -242,7 +242,7 @@ fn should_alter_the_sender_and_nonce() {
 		data: vec![],
 		nonce: Some(10.into()),
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 
 	let t = Transaction {
 		nonce: U256::zero(),
@@ -292,7 +292,7 @@ fn should_confirm_transaction_with_token() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 
 	let t = Transaction {
 		nonce: U256::zero(),
@@ -341,7 +341,7 @@ fn should_confirm_transaction_with_rlp() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 
 	let t = Transaction {
 		nonce: U256::zero(),
@@ -388,7 +388,7 @@ fn should_return_error_when_sender_does_not_match() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 
 	let t = Transaction {
 		nonce: U256::zero(),
@@ -435,7 +435,7 @@ fn should_confirm_sign_transaction_with_rlp() {
 		data: vec![],
 		nonce: None,
 		condition: None,
-	}), Origin::Unknown).unwrap();
+	}), Origin::Unknown(None)).unwrap();
 	assert_eq!(tester.signer.requests().len(), 1);
 
 	let t = Transaction {
@@ -491,7 +491,7 @@ fn should_confirm_data_sign_with_signature() {
 	tester.signer.add_request(ConfirmationPayload::EthSignMessage(
 		address,
 		vec![1, 2, 3, 4].into(),
-	), Origin::Unknown).unwrap();
+	), Origin::Unknown(None)).unwrap();
 	assert_eq!(tester.signer.requests().len(), 1);
 
 	let data_hash = eth_data_hash(vec![1, 2, 3, 4].into());
@@ -521,7 +521,7 @@ fn should_confirm_decrypt_with_phrase() {
 	tester.signer.add_request(ConfirmationPayload::Decrypt(
 		address,
 		vec![1, 2, 3, 4].into(),
-	), Origin::Unknown).unwrap();
+	), Origin::Unknown(None)).unwrap();
 	assert_eq!(tester.signer.requests().len(), 1);
 
 	let decrypted = serde_json::to_string(&RpcBytes::new(b"phrase".to_vec())).unwrap();
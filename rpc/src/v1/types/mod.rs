@@ -60,7 +60,7 @@ pub use self::histogram::Histogram;
 pub use self::index::Index;
 pub use self::log::Log;
 pub use self::node_kind::{NodeKind, Availability, Capability};
-pub use self::provenance::{Origin, DappId};
+pub use self::provenance::{Origin, DappId, DappIdError, DappOrigin, PackedOriginError, RenderPolicy, RpcOrigin, TransportKind};
 pub use self::receipt::Receipt;
 pub use self::rpc_settings::RpcSettings;
 pub use self::sync::{
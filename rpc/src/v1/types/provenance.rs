@@ -17,56 +17,828 @@
 //! Request Provenance
 
 use std::fmt;
+use std::cmp::Ordering;
+use std::net::SocketAddr;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{Error, Visitor, MapVisitor};
+use serde::ser::SerializeStruct;
 use ethcore::account_provider::DappId as EthDappId;
+use util::Hashable;
 use v1::types::H256;
 
 /// RPC request origin
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
+///
+/// Deserializes both from the tagged object form (e.g. `{"ws": "0x..."}`)
+/// and from the legacy bare variant name (e.g. `"signer"`), in which case
+/// the session is zero and strings are empty. `Unknown` without a reason
+/// is serialized as the bare `"unknown"`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Origin {
 	/// RPC server (includes request origin)
-	#[serde(rename="rpc")]
-	Rpc(String),
-	/// Dapps server (includes DappId)
-	#[serde(rename="dapp")]
-	Dapps(DappId),
+	Rpc(RpcOrigin),
+	/// Plain HTTP JSON-RPC (includes remote host)
+	Http(String),
+	/// Dapps server (includes DappId and the transport it came through)
+	Dapps(DappOrigin),
 	/// IPC server (includes session hash)
-	#[serde(rename="ipc")]
 	Ipc(H256),
 	/// WS server (includes session hash)
-	#[serde(rename="ws")]
 	Ws(H256),
 	/// Signer (includes session hash)
-	#[serde(rename="signer")]
 	Signer(H256),
-	/// Unknown
-	#[serde(rename="unknown")]
-	Unknown,
+	/// Pub/Sub notification (includes subscription id)
+	PubSub(H256),
+	/// Unknown (includes the reason the origin is unknown, if any)
+	Unknown(Option<String>),
+	/// Internal call, e.g. issued by the miner or a scheduled task (includes subsystem label)
+	Internal(String),
 }
 
+/// Transport a request with a known peer address came through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransportKind {
+	/// RPC server
+	Rpc,
+	/// Plain HTTP JSON-RPC
+	Http,
+}
+
+/// How much of an origin is rendered by `Origin::render`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RenderPolicy {
+	/// Render the origin with all its details.
+	Full,
+	/// Render the anonymized origin, with session hashes zeroed.
+	Redacted,
+	/// Render only the kind of the origin.
+	KindOnly,
+}
+
+/// Error decoding an origin from its packed form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackedOriginError {
+	/// Input ended before the origin was complete.
+	Truncated,
+	/// Unknown variant tag.
+	UnknownTag(u8),
+	/// Input continues past the end of the origin.
+	TrailingData,
+	/// A string payload is not valid UTF-8.
+	InvalidUtf8,
+	/// A dapp origin came through another dapp origin.
+	NestedDapps,
+}
+
+impl fmt::Display for PackedOriginError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			PackedOriginError::Truncated => write!(f, "Packed origin is truncated"),
+			PackedOriginError::UnknownTag(tag) => write!(f, "Unknown packed origin tag {}", tag),
+			PackedOriginError::TrailingData => write!(f, "Packed origin has trailing data"),
+			PackedOriginError::InvalidUtf8 => write!(f, "Packed origin contains invalid UTF-8"),
+			PackedOriginError::NestedDapps => write!(f, "Packed dapp origin is nested in another dapp origin"),
+		}
+	}
+}
+
+// tags of the packed origin variants.
+const PACKED_UNKNOWN: u8 = 0;
+const PACKED_UNKNOWN_REASON: u8 = 1;
+const PACKED_INTERNAL: u8 = 2;
+const PACKED_HTTP: u8 = 3;
+const PACKED_RPC: u8 = 4;
+const PACKED_RPC_ID: u8 = 5;
+const PACKED_DAPPS: u8 = 6;
+const PACKED_PUBSUB: u8 = 7;
+const PACKED_IPC: u8 = 8;
+const PACKED_WS: u8 = 9;
+const PACKED_SIGNER: u8 = 10;
+
 impl Default for Origin {
 	fn default() -> Self {
-		Origin::Unknown
+		Origin::Unknown(None)
+	}
+}
+
+impl Origin {
+	/// Create an origin for a peer connected through given transport.
+	///
+	/// The address is always formatted as `ip:port` (`[ip]:port` for IPv6).
+	pub fn from_socket(kind: TransportKind, addr: SocketAddr) -> Origin {
+		let addr = addr.to_string();
+		match kind {
+			TransportKind::Rpc => Origin::Rpc(addr.into()),
+			TransportKind::Http => Origin::Http(addr),
+		}
+	}
+
+	/// Create an origin from the value of an HTTP `Origin` or `Referer` header.
+	///
+	/// Well-formed values become a `Dapps` origin identified by the header's
+	/// lowercased host, including the port if any (the scheme, path, query and
+	/// fragment are dropped). Malformed values yield `Unknown` with a reason.
+	pub fn from_http_header(value: &str) -> Origin {
+		let malformed = || Origin::Unknown(Some("malformed origin header".into()));
+		let value = value.trim();
+
+		let scheme_end = match value.find("://") {
+			Some(idx) => idx,
+			None => return malformed(),
+		};
+		let scheme = &value[..scheme_end];
+		let host = &value[scheme_end + 3..host_end(value, scheme_end + 3)];
+
+		let valid_scheme = scheme.starts_with(|c| match c { 'a'...'z' | 'A'...'Z' => true, _ => false })
+			&& scheme.chars().all(|c| match c { 'a'...'z' | 'A'...'Z' | '0'...'9' | '+' | '-' | '.' => true, _ => false });
+		if !valid_scheme || !is_valid_host(host) {
+			return malformed();
+		}
+
+		match DappId::parse(&host.to_lowercase()) {
+			Ok(id) => Origin::Dapps(id.into()),
+			Err(_) => malformed(),
+		}
+	}
+
+	/// Returns a copy of this origin with per-session identifiers scrubbed.
+	///
+	/// Session hashes are replaced with zero, request ids are dropped and
+	/// URLs are truncated to their scheme and host, so only the kind of
+	/// transport remains distinguishable.
+	pub fn anonymized(&self) -> Origin {
+		match *self {
+			Origin::Rpc(ref origin) => Origin::Rpc(scheme_and_host(&origin.service).into()),
+			Origin::Http(ref host) => Origin::Http(scheme_and_host(host)),
+			Origin::Dapps(ref dapp) => Origin::Dapps(DappOrigin::new(scheme_and_host(&dapp.id.0), dapp.via.anonymized())),
+			Origin::Ipc(_) => Origin::Ipc(H256::default()),
+			Origin::Ws(_) => Origin::Ws(H256::default()),
+			Origin::Signer(_) => Origin::Signer(H256::default()),
+			Origin::PubSub(_) => Origin::PubSub(H256::default()),
+			Origin::Unknown(_) => Origin::Unknown(None),
+			Origin::Internal(ref subsystem) => Origin::Internal(subsystem.clone()),
+		}
+	}
+
+	/// Returns the session hash for session-based transports.
+	pub fn session(&self) -> Option<H256> {
+		match *self {
+			Origin::Ipc(ref session) | Origin::Ws(ref session) | Origin::Signer(ref session) => Some(session.clone()),
+			_ => None,
+		}
+	}
+
+	/// Render this origin for logging, revealing as much as the policy allows.
+	pub fn render(&self, policy: RenderPolicy) -> String {
+		match policy {
+			RenderPolicy::Full => self.to_string(),
+			RenderPolicy::Redacted => self.anonymized().to_string(),
+			RenderPolicy::KindOnly => self.kind_str().to_owned(),
+		}
+	}
+
+	/// Returns a low-cardinality label for the kind of this origin,
+	/// matching its serialized variant name.
+	pub fn kind_str(&self) -> &'static str {
+		match *self {
+			Origin::Rpc(_) => "rpc",
+			Origin::Http(_) => "http",
+			Origin::Dapps(_) => "dapp",
+			Origin::Ipc(_) => "ipc",
+			Origin::Ws(_) => "ws",
+			Origin::Signer(_) => "signer",
+			Origin::PubSub(_) => "pubsub",
+			Origin::Unknown(_) => "unknown",
+			Origin::Internal(_) => "internal",
+		}
+	}
+
+	/// Returns true if the origin is trusted to call sensitive methods.
+	///
+	/// Only local transports are trusted: `Signer` (authenticated UI sessions)
+	/// and `Ipc` (protected by filesystem permissions). Every other origin,
+	/// including `Unknown` and `Internal`, is untrusted.
+	pub fn is_trusted(&self) -> bool {
+		match *self {
+			Origin::Signer(_) | Origin::Ipc(_) => true,
+			Origin::Rpc(_) | Origin::Http(_) | Origin::Dapps(_) | Origin::Ws(_) | Origin::PubSub(_) | Origin::Unknown(_) | Origin::Internal(_) => false,
+		}
+	}
+
+	/// Returns the RPC namespaces this kind of origin may call by default.
+	///
+	/// Sensitive namespaces (`personal`, `parity_accounts`, `parity_set`) are
+	/// only available to trusted origins and `signer` only to the Signer itself.
+	/// Anonymous `Rpc` and `Http` origins get only `eth` and `net`; `Unknown`
+	/// origins get nothing.
+	pub fn default_namespaces(&self) -> &'static [&'static str] {
+		match *self {
+			Origin::Signer(_) => &["eth", "net", "web3", "parity", "personal", "parity_accounts", "parity_set", "signer"],
+			Origin::Ipc(_) => &["eth", "net", "web3", "parity", "personal", "parity_accounts", "parity_set"],
+			Origin::Ws(_) => &["eth", "net", "web3", "parity", "pubsub"],
+			Origin::Dapps(_) => &["eth", "net", "web3", "parity"],
+			Origin::PubSub(_) => &["pubsub"],
+			Origin::Rpc(_) | Origin::Http(_) | Origin::Internal(_) => &["eth", "net"],
+			Origin::Unknown(_) => &[],
+		}
+	}
+
+	/// Encode this origin into a compact binary form, meant for log and index
+	/// storage rather than the RPC wire.
+	///
+	/// The form is a 1-byte variant tag, followed by the 32-byte session hash for
+	/// session-based variants. Strings are stored as raw UTF-8 taking up the rest
+	/// of the input, except for the dapp id, which is prefixed with its 4-byte
+	/// big-endian length and followed by the packed transport.
+	pub fn to_packed(&self) -> Vec<u8> {
+		let mut packed = Vec::with_capacity(33);
+		self.pack_into(&mut packed);
+		packed
+	}
+
+	/// Decode an origin from the form produced by `to_packed`.
+	pub fn from_packed(packed: &[u8]) -> Result<Origin, PackedOriginError> {
+		let (&tag, payload) = packed.split_first().ok_or(PackedOriginError::Truncated)?;
+		let string = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| PackedOriginError::InvalidUtf8);
+		let session = |bytes: &[u8]| match bytes.len() {
+			32 => {
+				let mut hash = [0u8; 32];
+				hash.copy_from_slice(bytes);
+				Ok(H256(hash))
+			},
+			len if len < 32 => Err(PackedOriginError::Truncated),
+			_ => Err(PackedOriginError::TrailingData),
+		};
+
+		Ok(match tag {
+			PACKED_UNKNOWN if payload.is_empty() => Origin::Unknown(None),
+			PACKED_UNKNOWN => return Err(PackedOriginError::TrailingData),
+			PACKED_UNKNOWN_REASON => Origin::Unknown(Some(string(payload)?)),
+			PACKED_INTERNAL => Origin::Internal(string(payload)?),
+			PACKED_HTTP => Origin::Http(string(payload)?),
+			PACKED_RPC => Origin::Rpc(RpcOrigin { service: string(payload)?, id: None }),
+			PACKED_RPC_ID => {
+				if payload.len() < 8 {
+					return Err(PackedOriginError::Truncated);
+				}
+				let id = payload[..8].iter().fold(0u64, |id, &byte| (id << 8) | byte as u64);
+				Origin::Rpc(RpcOrigin::new(string(&payload[8..])?, id))
+			},
+			PACKED_DAPPS => {
+				if payload.len() < 4 {
+					return Err(PackedOriginError::Truncated);
+				}
+				let len = payload[..4].iter().fold(0usize, |len, &byte| (len << 8) | byte as usize);
+				if payload.len() - 4 < len {
+					return Err(PackedOriginError::Truncated);
+				}
+				// dapps never come through other dapps, so nesting is rejected before recursing.
+				if payload.get(4 + len) == Some(&PACKED_DAPPS) {
+					return Err(PackedOriginError::NestedDapps);
+				}
+				let id = DappId(string(&payload[4..4 + len])?);
+				Origin::Dapps(DappOrigin::new(id, Origin::from_packed(&payload[4 + len..])?))
+			},
+			PACKED_PUBSUB => Origin::PubSub(session(payload)?),
+			PACKED_IPC => Origin::Ipc(session(payload)?),
+			PACKED_WS => Origin::Ws(session(payload)?),
+			PACKED_SIGNER => Origin::Signer(session(payload)?),
+			tag => return Err(PackedOriginError::UnknownTag(tag)),
+		})
+	}
+
+	// appends the packed form of this origin.
+	fn pack_into(&self, out: &mut Vec<u8>) {
+		match *self {
+			Origin::Unknown(None) => out.push(PACKED_UNKNOWN),
+			Origin::Unknown(Some(ref reason)) => {
+				out.push(PACKED_UNKNOWN_REASON);
+				out.extend_from_slice(reason.as_bytes());
+			},
+			Origin::Internal(ref subsystem) => {
+				out.push(PACKED_INTERNAL);
+				out.extend_from_slice(subsystem.as_bytes());
+			},
+			Origin::Http(ref host) => {
+				out.push(PACKED_HTTP);
+				out.extend_from_slice(host.as_bytes());
+			},
+			Origin::Rpc(ref origin) => {
+				match origin.id {
+					Some(id) => {
+						out.push(PACKED_RPC_ID);
+						out.extend((0..8).rev().map(|i| (id >> (8 * i)) as u8));
+					},
+					None => out.push(PACKED_RPC),
+				}
+				out.extend_from_slice(origin.service.as_bytes());
+			},
+			Origin::Dapps(ref dapp) => {
+				let id = dapp.id.0.as_bytes();
+				out.push(PACKED_DAPPS);
+				out.extend((0..4).rev().map(|i| (id.len() >> (8 * i)) as u8));
+				out.extend_from_slice(id);
+				dapp.via.pack_into(out);
+			},
+			Origin::PubSub(ref session) => {
+				out.push(PACKED_PUBSUB);
+				out.extend_from_slice(&session.0);
+			},
+			Origin::Ipc(ref session) => {
+				out.push(PACKED_IPC);
+				out.extend_from_slice(&session.0);
+			},
+			Origin::Ws(ref session) => {
+				out.push(PACKED_WS);
+				out.extend_from_slice(&session.0);
+			},
+			Origin::Signer(ref session) => {
+				out.push(PACKED_SIGNER);
+				out.extend_from_slice(&session.0);
+			},
+		}
+	}
+
+	/// Returns true if both origins came through the same kind of transport,
+	/// regardless of their session or other inner values.
+	pub fn same_kind(&self, other: &Origin) -> bool {
+		self.priority() == other.priority()
+	}
+
+	// priority of the origin's kind, higher is more important; distinct for every variant.
+	fn priority(&self) -> u8 {
+		match *self {
+			Origin::Unknown(_) => 0,
+			Origin::Internal(_) => 1,
+			Origin::Http(_) => 2,
+			Origin::Rpc(_) => 3,
+			Origin::Dapps(_) => 4,
+			Origin::PubSub(_) => 5,
+			Origin::Ipc(_) => 6,
+			Origin::Ws(_) => 7,
+			Origin::Signer(_) => 8,
+		}
+	}
+}
+
+/// Origins are ordered by the priority of their kind:
+/// `Signer > Ws > Ipc > PubSub > Dapps > Rpc > Http > Internal > Unknown`.
+/// Origins of the same kind are ordered by their inner value.
+impl Ord for Origin {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.priority().cmp(&other.priority()).then_with(|| match (self, other) {
+			(&Origin::Rpc(ref a), &Origin::Rpc(ref b)) => a.cmp(b),
+			(&Origin::Http(ref a), &Origin::Http(ref b)) => a.cmp(b),
+			(&Origin::Dapps(ref a), &Origin::Dapps(ref b)) => a.cmp(b),
+			(&Origin::Ipc(ref a), &Origin::Ipc(ref b)) => a.cmp(b),
+			(&Origin::Ws(ref a), &Origin::Ws(ref b)) => a.cmp(b),
+			(&Origin::Signer(ref a), &Origin::Signer(ref b)) => a.cmp(b),
+			(&Origin::PubSub(ref a), &Origin::PubSub(ref b)) => a.cmp(b),
+			(&Origin::Unknown(ref a), &Origin::Unknown(ref b)) => a.cmp(b),
+			(&Origin::Internal(ref a), &Origin::Internal(ref b)) => a.cmp(b),
+			_ => Ordering::Equal,
+		})
 	}
 }
 
+impl PartialOrd for Origin {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+// truncates an URL to its scheme and host, dropping path, query and fragment.
+fn scheme_and_host(url: &str) -> String {
+	let start = url.find("://").map_or(0, |idx| idx + 3);
+	url[..host_end(url, start)].to_owned()
+}
+
+// index of the end of the host part of an URL which starts at `start`.
+fn host_end(url: &str, start: usize) -> usize {
+	url[start..].find(|c| c == '/' || c == '?' || c == '#').map_or(url.len(), |idx| start + idx)
+}
+
+// checks that host is a domain name or IPv4 address, or an IPv6 address in brackets,
+// optionally followed by a numeric port.
+fn is_valid_host(host: &str) -> bool {
+	let split = if host.starts_with('[') {
+		host.find(']').map(|idx| idx + 1)
+	} else {
+		Some(host.find(':').unwrap_or(host.len()))
+	};
+	let (name, port) = match split {
+		Some(idx) => (&host[..idx], &host[idx..]),
+		None => return false,
+	};
+
+	let valid_port = port.is_empty() || (port.len() > 1 && port.starts_with(':') && port[1..].chars().all(|c| c.is_digit(10)));
+	let valid_name = if name.starts_with('[') {
+		let address = &name[1..name.len() - 1];
+		!address.is_empty() && address.chars().all(|c| c.is_digit(16) || c == ':' || c == '.')
+	} else {
+		!name.is_empty() && name.chars().all(|c| match c { 'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' => true, _ => false })
+	};
+
+	valid_name && valid_port
+}
+
 impl fmt::Display for Origin {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
-			Origin::Rpc(ref origin) => write!(f, "RPC (service: {})", origin),
-			Origin::Dapps(ref origin) => write!(f, "Dapp {}", origin),
+			Origin::Rpc(ref origin) => match origin.id {
+				Some(id) => write!(f, "RPC (service: {}, request: {})", origin.service, id),
+				None => write!(f, "RPC (service: {})", origin.service),
+			},
+			Origin::Http(ref host) => write!(f, "HTTP (host: {})", host),
+			Origin::Dapps(ref origin) => match *origin.via {
+				Origin::Unknown(None) => write!(f, "Dapp {}", origin.id),
+				ref via => write!(f, "Dapp {} (via {})", origin.id, via),
+			},
 			Origin::Ipc(ref session) => write!(f, "IPC (session: {})", session),
 			Origin::Ws(ref session) => write!(f, "WebSocket (session: {})", session),
 			Origin::Signer(ref session) => write!(f, "UI (session: {})", session),
-			Origin::Unknown => write!(f, "unknown origin"),
+			Origin::PubSub(ref subscription) => write!(f, "PubSub (subscription: {})", subscription),
+			Origin::Unknown(None) => write!(f, "unknown origin"),
+			Origin::Unknown(Some(ref reason)) => write!(f, "unknown origin ({})", reason),
+			Origin::Internal(ref subsystem) => write!(f, "Internal (subsystem: {})", subsystem),
+		}
+	}
+}
+
+impl Serialize for Origin {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+		match *self {
+			Origin::Rpc(ref origin) => serializer.serialize_newtype_variant("Origin", 0, "rpc", origin),
+			Origin::Http(ref host) => serializer.serialize_newtype_variant("Origin", 1, "http", host),
+			Origin::Dapps(ref origin) => serializer.serialize_newtype_variant("Origin", 2, "dapp", origin),
+			Origin::Ipc(ref session) => serializer.serialize_newtype_variant("Origin", 3, "ipc", session),
+			Origin::Ws(ref session) => serializer.serialize_newtype_variant("Origin", 4, "ws", session),
+			Origin::Signer(ref session) => serializer.serialize_newtype_variant("Origin", 5, "signer", session),
+			Origin::PubSub(ref subscription) => serializer.serialize_newtype_variant("Origin", 6, "pubsub", subscription),
+			Origin::Unknown(None) => serializer.serialize_unit_variant("Origin", 7, "unknown"),
+			Origin::Unknown(Some(ref reason)) => serializer.serialize_newtype_variant("Origin", 7, "unknown", reason),
+			Origin::Internal(ref subsystem) => serializer.serialize_newtype_variant("Origin", 8, "internal", subsystem),
+		}
+	}
+}
+
+impl Deserialize for Origin {
+	fn deserialize<D>(deserializer: D) -> Result<Origin, D::Error> where D: Deserializer {
+		deserializer.deserialize(OriginVisitor)
+	}
+}
+
+struct OriginVisitor;
+
+impl Visitor for OriginVisitor {
+	type Value = Origin;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		write!(formatter, "an origin object or a bare origin kind")
+	}
+
+	fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> where E: Error {
+		match value {
+			"rpc" => Ok(Origin::Rpc(RpcOrigin::default())),
+			"http" => Ok(Origin::Http(String::new())),
+			"dapp" => Ok(Origin::Dapps(DappOrigin::default())),
+			"ipc" => Ok(Origin::Ipc(H256::default())),
+			"ws" => Ok(Origin::Ws(H256::default())),
+			"signer" => Ok(Origin::Signer(H256::default())),
+			"pubsub" => Ok(Origin::PubSub(H256::default())),
+			"unknown" => Ok(Origin::Unknown(None)),
+			"internal" => Ok(Origin::Internal(String::new())),
+			_ => Err(Error::custom(format!("unknown origin `{}`", value))),
+		}
+	}
+
+	fn visit_string<E>(self, value: String) -> Result<Self::Value, E> where E: Error {
+		self.visit_str(value.as_ref())
+	}
+
+	fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error> where V: MapVisitor {
+		let key = match visitor.visit_key::<String>()? {
+			Some(key) => key,
+			None => return Err(Error::custom("missing origin")),
+		};
+
+		let origin = match key.as_str() {
+			"rpc" => Origin::Rpc(visitor.visit_value()?),
+			"http" => Origin::Http(visitor.visit_value()?),
+			"dapp" => Origin::Dapps(visitor.visit_value()?),
+			"ipc" => Origin::Ipc(visitor.visit_value()?),
+			"ws" => Origin::Ws(visitor.visit_value()?),
+			"signer" => Origin::Signer(visitor.visit_value()?),
+			"pubsub" => Origin::PubSub(visitor.visit_value()?),
+			"unknown" => Origin::Unknown(visitor.visit_value()?),
+			"internal" => Origin::Internal(visitor.visit_value()?),
+			_ => return Err(Error::custom(format!("unknown origin `{}`", key))),
+		};
+
+		if visitor.visit_key::<String>()?.is_some() {
+			return Err(Error::custom("expected a single origin"));
+		}
+
+		Ok(origin)
+	}
+}
+
+/// Origin of an RPC server request.
+///
+/// Serialized as just the service string when no request id is present,
+/// and as a `{ "service", "id" }` object otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RpcOrigin {
+	/// Service the request originated from.
+	pub service: String,
+	/// Sequence number of the request, if known.
+	pub id: Option<u64>,
+}
+
+impl RpcOrigin {
+	/// Create an RPC origin for the given request of a service.
+	pub fn new<T: Into<String>>(service: T, id: u64) -> Self {
+		RpcOrigin {
+			service: service.into(),
+			id: Some(id),
+		}
+	}
+}
+
+impl From<String> for RpcOrigin {
+	fn from(service: String) -> Self {
+		RpcOrigin {
+			service: service,
+			id: None,
+		}
+	}
+}
+
+impl<'a> From<&'a str> for RpcOrigin {
+	fn from(service: &'a str) -> Self {
+		service.to_owned().into()
+	}
+}
+
+impl Serialize for RpcOrigin {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+		match self.id {
+			None => serializer.serialize_str(&self.service),
+			Some(ref id) => {
+				let mut state = serializer.serialize_struct("RpcOrigin", 2)?;
+				state.serialize_field("service", &self.service)?;
+				state.serialize_field("id", id)?;
+				state.end()
+			}
+		}
+	}
+}
+
+impl Deserialize for RpcOrigin {
+	fn deserialize<D>(deserializer: D) -> Result<RpcOrigin, D::Error> where D: Deserializer {
+		deserializer.deserialize(RpcOriginVisitor)
+	}
+}
+
+struct RpcOriginVisitor;
+
+impl Visitor for RpcOriginVisitor {
+	type Value = RpcOrigin;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		write!(formatter, "a service string or an object with service and id")
+	}
+
+	fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> where E: Error {
+		Ok(value.into())
+	}
+
+	fn visit_string<E>(self, value: String) -> Result<Self::Value, E> where E: Error {
+		Ok(value.into())
+	}
+
+	fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error> where V: MapVisitor {
+		let mut service = None;
+		let mut id = None;
+
+		while let Some(key) = visitor.visit_key::<String>()? {
+			match key.as_str() {
+				"service" => service = Some(visitor.visit_value()?),
+				"id" => id = visitor.visit_value()?,
+				_ => return Err(Error::custom(format!("unknown field `{}`", key))),
+			}
+		}
+
+		Ok(RpcOrigin {
+			service: service.ok_or_else(|| Error::custom("missing service"))?,
+			id: id,
+		})
+	}
+}
+
+/// Origin of a Dapps server request.
+///
+/// Serialized as just the dapp id when the underlying transport is unknown
+/// for no particular reason,
+/// and as an `{ "id", "via" }` object otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DappOrigin {
+	/// Id of the dapp the request originated from.
+	pub id: DappId,
+	/// Transport the dapp's request came through.
+	pub via: Box<Origin>,
+}
+
+impl DappOrigin {
+	/// Create a dapp origin for a request that came through given transport.
+	pub fn new<T: Into<DappId>>(id: T, via: Origin) -> Self {
+		DappOrigin {
+			id: id.into(),
+			via: Box::new(via),
+		}
+	}
+}
+
+impl From<DappId> for DappOrigin {
+	fn from(id: DappId) -> Self {
+		DappOrigin::new(id, Origin::Unknown(None))
+	}
+}
+
+impl From<String> for DappOrigin {
+	fn from(id: String) -> Self {
+		DappId::from(id).into()
+	}
+}
+
+impl<'a> From<&'a str> for DappOrigin {
+	fn from(id: &'a str) -> Self {
+		DappId::from(id).into()
+	}
+}
+
+impl Serialize for DappOrigin {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+		match *self.via {
+			Origin::Unknown(None) => self.id.serialize(serializer),
+			ref via => {
+				let mut state = serializer.serialize_struct("DappOrigin", 2)?;
+				state.serialize_field("id", &self.id)?;
+				state.serialize_field("via", via)?;
+				state.end()
+			}
 		}
 	}
 }
 
+impl Deserialize for DappOrigin {
+	fn deserialize<D>(deserializer: D) -> Result<DappOrigin, D::Error> where D: Deserializer {
+		deserializer.deserialize(DappOriginVisitor)
+	}
+}
+
+struct DappOriginVisitor;
+
+impl Visitor for DappOriginVisitor {
+	type Value = DappOrigin;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		write!(formatter, "a dapp id string or an object with id and via")
+	}
+
+	fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> where E: Error {
+		Ok(DappId(value.to_owned()).into())
+	}
+
+	fn visit_string<E>(self, value: String) -> Result<Self::Value, E> where E: Error {
+		Ok(DappId(value).into())
+	}
+
+	fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error> where V: MapVisitor {
+		let mut id = None;
+		let mut via = None;
+
+		while let Some(key) = visitor.visit_key::<String>()? {
+			match key.as_str() {
+				"id" => id = Some(visitor.visit_value()?),
+				"via" => via = Some(visitor.visit_value()?),
+				_ => return Err(Error::custom(format!("unknown field `{}`", key))),
+			}
+		}
+
+		Ok(DappOrigin {
+			id: id.ok_or_else(|| Error::custom("missing id"))?,
+			via: Box::new(via.unwrap_or_default()),
+		})
+	}
+}
+
+/// Maximal length (in bytes) of a `DappId` accepted by `DappId::parse`.
+pub const MAX_DAPP_ID_LEN: usize = 256;
+
 /// Dapplication Internal Id
 #[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct DappId(pub String);
 
+/// Error parsing a `DappId`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DappIdError {
+	/// Id is empty.
+	Empty,
+	/// Id is longer than allowed (actual length, maximal length).
+	TooLong(usize, usize),
+	/// Id contains a control character.
+	ControlCharacter,
+}
+
+impl fmt::Display for DappIdError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			DappIdError::Empty => write!(f, "Dapp id is empty"),
+			DappIdError::TooLong(len, max) => write!(f, "Dapp id is too long ({} bytes, max {})", len, max),
+			DappIdError::ControlCharacter => write!(f, "Dapp id contains control characters"),
+		}
+	}
+}
+
+impl DappId {
+	/// Parse and normalize a dapp id, rejecting ids longer than `MAX_DAPP_ID_LEN`.
+	pub fn parse(s: &str) -> Result<DappId, DappIdError> {
+		DappId::parse_with_max_len(s, MAX_DAPP_ID_LEN)
+	}
+
+	/// Parse and normalize a dapp id, rejecting ids longer than `max_len` bytes.
+	pub fn parse_with_max_len(s: &str, max_len: usize) -> Result<DappId, DappIdError> {
+		if s.is_empty() {
+			return Err(DappIdError::Empty);
+		}
+		if s.len() > max_len {
+			return Err(DappIdError::TooLong(s.len(), max_len));
+		}
+		if s.chars().any(char::is_control) {
+			return Err(DappIdError::ControlCharacter);
+		}
+
+		Ok(DappId(normalize_dapp_id(s)))
+	}
+
+	/// Returns a copy of this id with scheme and host of URL-like ids lowercased.
+	///
+	/// Ids created through `From` or `parse` are already normalized, but ones
+	/// constructed directly or deserialized may not be.
+	pub fn normalized(&self) -> DappId {
+		DappId(normalize_dapp_id(&self.0))
+	}
+
+	/// Compare ids after normalization, so that ids differing only in the case
+	/// of their scheme or host are equal. The derived `Ord` compares exactly.
+	pub fn cmp_normalized(&self, other: &DappId) -> Ordering {
+		normalize_dapp_id(&self.0).cmp(&normalize_dapp_id(&other.0))
+	}
+
+	/// Returns the keccak hash of the normalized id, for bucketing dapps
+	/// consistently across subsystems.
+	pub fn stable_hash(&self) -> H256 {
+		normalize_dapp_id(&self.0).as_bytes().sha3().into()
+	}
+
+	/// Check whether this id is matched by an allowlist pattern.
+	///
+	/// Patterns either match the id exactly, or start with `*.` and match URL ids
+	/// whose host is a subdomain of the rest of the pattern (e.g. `*.parity.io`).
+	pub fn matches(&self, pattern: &str) -> bool {
+		if self.0 == pattern {
+			return true;
+		}
+
+		if !pattern.starts_with("*.") {
+			return false;
+		}
+
+		let start = match self.0.find("://") {
+			Some(idx) => idx + 3,
+			None => return false,
+		};
+		let host = &self.0[start..host_end(&self.0, start)];
+		let host = host.rfind(':').map_or(host, |idx| &host[..idx]);
+		let suffix = pattern[1..].to_lowercase();
+
+		host.len() > suffix.len() && host.ends_with(&suffix)
+	}
+}
+
+// lowercases scheme and host of URL-like ids, other ids are returned unchanged.
+fn normalize_dapp_id(s: &str) -> String {
+	match s.find("://") {
+		Some(idx) => {
+			let end = host_end(s, idx + 3);
+			let mut normalized = s[..end].to_lowercase();
+			normalized.push_str(&s[end..]);
+			normalized
+		},
+		None => s.to_owned(),
+	}
+}
+
 impl fmt::Display for DappId {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}", self.0)
@@ -81,19 +853,19 @@ impl Into<String> for DappId {
 
 impl From<String> for DappId {
 	fn from(s: String) -> Self {
-		DappId(s)
+		DappId(normalize_dapp_id(&s))
 	}
 }
 
 impl<'a> From<&'a str> for DappId {
 	fn from(s: &'a str) -> Self {
-		DappId(s.to_owned())
+		DappId(normalize_dapp_id(s))
 	}
 }
 
 impl From<EthDappId> for DappId {
 	fn from(id: EthDappId) -> Self {
-		DappId(id.into())
+		DappId::from(Into::<String>::into(id))
 	}
 }
 
@@ -106,7 +878,11 @@ impl Into<EthDappId> for DappId {
 #[cfg(test)]
 mod tests {
 	use serde_json;
-	use super::{DappId, Origin};
+	use ethcore::account_provider::DappId as EthDappId;
+	use v1::types::H256;
+	use std::cmp::Ordering;
+	use std::net::SocketAddr;
+	use super::{DappId, DappIdError, DappOrigin, Origin, PackedOriginError, RenderPolicy, RpcOrigin, TransportKind, MAX_DAPP_ID_LEN};
 
 	#[test]
 	fn should_serialize_origin() {
@@ -115,8 +891,10 @@ mod tests {
 		let o2 = Origin::Dapps("http://parity.io".into());
 		let o3 = Origin::Ipc(5.into());
 		let o4 = Origin::Signer(10.into());
-		let o5 = Origin::Unknown;
+		let o5 = Origin::Unknown(None);
 		let o6 = Origin::Ws(5.into());
+		let o7 = Origin::Http("localhost:8545".into());
+		let o8 = Origin::PubSub(5.into());
 
 		// when
 		let res1 = serde_json::to_string(&o1).unwrap();
@@ -125,6 +903,8 @@ mod tests {
 		let res4 = serde_json::to_string(&o4).unwrap();
 		let res5 = serde_json::to_string(&o5).unwrap();
 		let res6 = serde_json::to_string(&o6).unwrap();
+		let res7 = serde_json::to_string(&o7).unwrap();
+		let res8 = serde_json::to_string(&o8).unwrap();
 
 		// then
 		assert_eq!(res1, r#"{"rpc":"test service"}"#);
@@ -133,6 +913,383 @@ mod tests {
 		assert_eq!(res4, r#"{"signer":"0x000000000000000000000000000000000000000000000000000000000000000a"}"#);
 		assert_eq!(res5, r#""unknown""#);
 		assert_eq!(res6, r#"{"ws":"0x0000000000000000000000000000000000000000000000000000000000000005"}"#);
+		assert_eq!(res7, r#"{"http":"localhost:8545"}"#);
+		assert_eq!(res8, r#"{"pubsub":"0x0000000000000000000000000000000000000000000000000000000000000005"}"#);
+	}
+
+	#[test]
+	fn should_serialize_rpc_origin() {
+		// given
+		let legacy = Origin::Rpc("test service".into());
+		let with_id = Origin::Rpc(RpcOrigin::new("test service", 5));
+
+		// when
+		let res1 = serde_json::to_string(&legacy).unwrap();
+		let res2 = serde_json::to_string(&with_id).unwrap();
+
+		// then
+		assert_eq!(res1, r#"{"rpc":"test service"}"#);
+		assert_eq!(res2, r#"{"rpc":{"service":"test service","id":5}}"#);
+		assert_eq!(serde_json::from_str::<Origin>(&res1).unwrap(), legacy);
+		assert_eq!(serde_json::from_str::<Origin>(&res2).unwrap(), with_id);
+	}
+
+	#[test]
+	fn should_serialize_unknown_origin() {
+		// given
+		let bare = Origin::Unknown(None);
+		let with_reason = Origin::Unknown(Some("missing header".into()));
+
+		// when
+		let res1 = serde_json::to_string(&bare).unwrap();
+		let res2 = serde_json::to_string(&with_reason).unwrap();
+
+		// then
+		assert_eq!(res1, r#""unknown""#);
+		assert_eq!(res2, r#"{"unknown":"missing header"}"#);
+		assert_eq!(serde_json::from_str::<Origin>(&res1).unwrap(), bare);
+		assert_eq!(serde_json::from_str::<Origin>(&res2).unwrap(), with_reason);
+		assert_eq!(serde_json::from_str::<Origin>(r#"{"unknown":null}"#).unwrap(), bare);
+		assert_eq!(Origin::default(), bare);
+		assert_eq!(with_reason.anonymized(), bare);
+		assert_eq!(with_reason.kind_str(), "unknown");
+	}
+
+	#[test]
+	fn should_create_origin_from_http_header() {
+		// given
+		let plain = "https://Wallet.Parity.io";
+		let with_path = "http://localhost:8180/#/accounts?x=1";
+		let with_ipv6 = "http://[::1]:8180";
+		let invalid = vec![
+			"null", "", "://parity.io", "http://", "http://par ity.io", "1http://parity.io",
+			"http://parity.io:", "http://parity.io:80:80", "http://parity.io:port", "http://[::1", "http://::1",
+			"http://[]:8180", "http://[::1]8180", "http://pa]rity.io",
+		];
+
+		// when
+		let plain = Origin::from_http_header(plain);
+		let with_path = Origin::from_http_header(with_path);
+		let with_ipv6 = Origin::from_http_header(with_ipv6);
+
+		// then
+		assert_eq!(plain, Origin::Dapps("wallet.parity.io".into()));
+		assert_eq!(with_path, Origin::Dapps("localhost:8180".into()));
+		assert_eq!(with_ipv6, Origin::Dapps("[::1]:8180".into()));
+		for header in invalid {
+			assert_eq!(Origin::from_http_header(header), Origin::Unknown(Some("malformed origin header".into())), "{}", header);
+		}
+	}
+
+	#[test]
+	fn should_serialize_internal_origin() {
+		// given
+		let origin = Origin::Internal("miner".into());
+
+		// when
+		let res = serde_json::to_string(&origin).unwrap();
+
+		// then
+		assert_eq!(res, r#"{"internal":"miner"}"#);
+		assert_eq!(serde_json::from_str::<Origin>(&res).unwrap(), origin);
+		assert_eq!(format!("{}", origin), "Internal (subsystem: miner)");
+		assert_eq!(origin.kind_str(), "internal");
+		assert_eq!(origin.anonymized(), origin);
+		assert!(!origin.is_trusted());
+	}
+
+	#[test]
+	fn should_serialize_dapp_origin() {
+		// given
+		let simple = Origin::Dapps("http://parity.io".into());
+		let nested = Origin::Dapps(DappOrigin::new("http://parity.io", Origin::Ws(5.into())));
+
+		// when
+		let res1 = serde_json::to_string(&simple).unwrap();
+		let res2 = serde_json::to_string(&nested).unwrap();
+
+		// then
+		assert_eq!(res1, r#"{"dapp":"http://parity.io"}"#);
+		assert_eq!(res2, r#"{"dapp":{"id":"http://parity.io","via":{"ws":"0x0000000000000000000000000000000000000000000000000000000000000005"}}}"#);
+		assert_eq!(serde_json::from_str::<Origin>(&res1).unwrap(), simple);
+		assert_eq!(serde_json::from_str::<Origin>(&res2).unwrap(), nested);
+		assert_eq!(serde_json::from_str::<Origin>(r#"{"dapp":{"id":"http://parity.io"}}"#).unwrap(), simple);
+	}
+
+	#[test]
+	fn should_order_origins_by_priority() {
+		// given
+		let mut origins = vec![
+			Origin::Unknown(None),
+			Origin::Internal("miner".into()),
+			Origin::Rpc("b".into()),
+			Origin::Signer(5.into()),
+			Origin::Dapps("app".into()),
+			Origin::Http("localhost".into()),
+			Origin::Ipc(1.into()),
+			Origin::PubSub(2.into()),
+			Origin::Rpc("a".into()),
+			Origin::Ws(3.into()),
+			Origin::Signer(1.into()),
+		];
+
+		// when
+		origins.sort();
+
+		// then
+		assert_eq!(origins, vec![
+			Origin::Unknown(None),
+			Origin::Internal("miner".into()),
+			Origin::Http("localhost".into()),
+			Origin::Rpc("a".into()),
+			Origin::Rpc("b".into()),
+			Origin::Dapps("app".into()),
+			Origin::PubSub(2.into()),
+			Origin::Ipc(1.into()),
+			Origin::Ws(3.into()),
+			Origin::Signer(1.into()),
+			Origin::Signer(5.into()),
+		]);
+	}
+
+	#[test]
+	fn should_anonymize_origins() {
+		// given
+		let ws1 = Origin::Ws(5.into());
+		let ws2 = Origin::Ws(10.into());
+		let rpc = Origin::Rpc(RpcOrigin::new("http://parity.io/path?query=1", 5));
+		let dapp = Origin::Dapps("https://wallet.ethereum.org/#/accounts".into());
+
+		// when
+		let anon_ws1 = ws1.anonymized();
+		let anon_ws2 = ws2.anonymized();
+		let anon_rpc = rpc.anonymized();
+		let anon_dapp = dapp.anonymized();
+
+		// then
+		assert_eq!(ws1, Origin::Ws(5.into()));
+		assert_eq!(anon_ws1, anon_ws2);
+		assert_eq!(anon_ws1, Origin::Ws(H256::default()));
+		assert_eq!(anon_rpc, Origin::Rpc("http://parity.io".into()));
+		assert_eq!(anon_dapp, Origin::Dapps("https://wallet.ethereum.org".into()));
+		assert_eq!(anon_rpc.anonymized(), anon_rpc);
+		assert_eq!(anon_dapp.anonymized(), anon_dapp);
+		assert_eq!(
+			Origin::Dapps(DappOrigin::new("https://wallet.ethereum.org/#/", Origin::Ws(5.into()))).anonymized(),
+			Origin::Dapps(DappOrigin::new("https://wallet.ethereum.org", Origin::Ws(H256::default())))
+		);
+		assert_eq!(Origin::Unknown(None).anonymized(), Origin::Unknown(None));
+	}
+
+	#[test]
+	fn should_return_session_hash() {
+		assert_eq!(Origin::Ipc(1.into()).session(), Some(1.into()));
+		assert_eq!(Origin::Ws(2.into()).session(), Some(2.into()));
+		assert_eq!(Origin::Signer(3.into()).session(), Some(3.into()));
+		assert_eq!(Origin::PubSub(4.into()).session(), None);
+		assert_eq!(Origin::Rpc("rpc".into()).session(), None);
+		assert_eq!(Origin::Http("localhost".into()).session(), None);
+		assert_eq!(Origin::Dapps("dapp".into()).session(), None);
+		assert_eq!(Origin::Unknown(None).session(), None);
+	}
+
+	#[test]
+	fn should_render_origin_according_to_policy() {
+		// given
+		let session: H256 = serde_json::from_str(r#""0xff00000000000000000000000000000000000000000000000000000000000005""#).unwrap();
+		let signer = Origin::Signer(session);
+
+		// when
+		let full = signer.render(RenderPolicy::Full);
+		let redacted = signer.render(RenderPolicy::Redacted);
+		let kind = signer.render(RenderPolicy::KindOnly);
+
+		// then
+		assert_eq!(full, signer.to_string());
+		assert_eq!(redacted, Origin::Signer(H256::default()).to_string());
+		assert!(full != redacted);
+		assert_eq!(kind, "signer");
+	}
+
+	#[test]
+	fn should_return_default_namespaces() {
+		// given
+		let signer = Origin::Signer(1.into());
+		let dapps = Origin::Dapps("dapp".into());
+		let rpc = Origin::Rpc("rpc".into());
+
+		// when
+		let signer = signer.default_namespaces();
+		let dapps = dapps.default_namespaces();
+		let rpc = rpc.default_namespaces();
+
+		// then
+		assert_eq!(signer, &["eth", "net", "web3", "parity", "personal", "parity_accounts", "parity_set", "signer"]);
+		assert_eq!(dapps, &["eth", "net", "web3", "parity"]);
+		assert_eq!(rpc, &["eth", "net"]);
+		assert!(!dapps.contains(&"personal") && !dapps.contains(&"signer"));
+		assert!(Origin::Unknown(None).default_namespaces().is_empty());
+	}
+
+	#[test]
+	fn should_roundtrip_packed_origins() {
+		// given
+		let session: H256 = serde_json::from_str(r#""0xff00000000000000000000000000000000000000000000000000000000000005""#).unwrap();
+		let origins = vec![
+			Origin::Rpc("rpc".into()),
+			Origin::Rpc(RpcOrigin::new("rpc", 0x0102030405060708)),
+			Origin::Http("localhost:8545".into()),
+			Origin::Dapps("http://parity.io".into()),
+			Origin::Dapps(DappOrigin::new("dapp", Origin::Ws(session.clone()))),
+			Origin::Dapps(DappOrigin::new("", Origin::Rpc("".into()))),
+			Origin::Ipc(session.clone()),
+			Origin::Ws(session.clone()),
+			Origin::Signer(session.clone()),
+			Origin::PubSub(session.clone()),
+			Origin::Unknown(None),
+			Origin::Unknown(Some("malformed origin header".into())),
+			Origin::Internal("miner".into()),
+			Origin::Internal("".into()),
+		];
+
+		for origin in origins {
+			// when
+			let packed = origin.to_packed();
+
+			// then
+			assert_eq!(Origin::from_packed(&packed), Ok(origin));
+		}
+		assert_eq!(Origin::Signer(session).to_packed().len(), 33);
+	}
+
+	#[test]
+	fn should_reject_malformed_packed_origins() {
+		let signer = Origin::Signer(1.into()).to_packed();
+		let mut trailing = signer.clone();
+		trailing.push(0);
+
+		assert_eq!(Origin::from_packed(&[]), Err(PackedOriginError::Truncated));
+		assert_eq!(Origin::from_packed(&signer[..32]), Err(PackedOriginError::Truncated));
+		assert_eq!(Origin::from_packed(&trailing), Err(PackedOriginError::TrailingData));
+		assert_eq!(Origin::from_packed(&[0, 0]), Err(PackedOriginError::TrailingData));
+		assert_eq!(Origin::from_packed(&[255]), Err(PackedOriginError::UnknownTag(255)));
+		assert_eq!(Origin::from_packed(&[3, 0xff]), Err(PackedOriginError::InvalidUtf8));
+		assert_eq!(Origin::from_packed(&[6, 0, 0, 0, 5, b'd']), Err(PackedOriginError::Truncated));
+	}
+
+	#[test]
+	fn should_reject_nested_packed_dapps() {
+		let nested = Origin::Dapps(DappOrigin::new("outer", Origin::Dapps(DappOrigin::new("inner", Origin::Unknown(None)))));
+		assert_eq!(Origin::from_packed(&nested.to_packed()), Err(PackedOriginError::NestedDapps));
+
+		// deeply nested input fails without recursing through it
+		let deep: Vec<u8> = (0..100_000).flat_map(|_| vec![6, 0, 0, 0, 0]).chain(Some(0)).collect();
+		assert_eq!(Origin::from_packed(&deep), Err(PackedOriginError::NestedDapps));
+	}
+
+	#[test]
+	fn kind_str_should_match_serialized_name() {
+		// given
+		let origins = vec![
+			Origin::Rpc("rpc".into()),
+			Origin::Http("localhost".into()),
+			Origin::Dapps("dapp".into()),
+			Origin::Ipc(1.into()),
+			Origin::Ws(2.into()),
+			Origin::Signer(3.into()),
+			Origin::PubSub(4.into()),
+			Origin::Unknown(None),
+			Origin::Internal("miner".into()),
+		];
+
+		for origin in origins {
+			// when
+			let serialized = serde_json::to_value(&origin).unwrap();
+			let name = match serialized {
+				serde_json::Value::String(name) => name,
+				serde_json::Value::Object(map) => map.keys().next().unwrap().clone(),
+				other => panic!("unexpected serialized origin: {:?}", other),
+			};
+
+			// then
+			assert_eq!(origin.kind_str(), name);
+		}
+	}
+
+	#[test]
+	fn should_create_origin_from_ipv4_socket() {
+		// given
+		let addr: SocketAddr = "127.0.0.1:8545".parse().unwrap();
+
+		// when
+		let rpc = Origin::from_socket(TransportKind::Rpc, addr);
+		let http = Origin::from_socket(TransportKind::Http, addr);
+
+		// then
+		assert_eq!(rpc, Origin::Rpc("127.0.0.1:8545".into()));
+		assert_eq!(http, Origin::Http("127.0.0.1:8545".into()));
+	}
+
+	#[test]
+	fn should_create_origin_from_ipv6_socket() {
+		// given
+		let addr: SocketAddr = "[0:0:0:0:0:0:0:1]:8545".parse().unwrap();
+
+		// when
+		let rpc = Origin::from_socket(TransportKind::Rpc, addr);
+		let http = Origin::from_socket(TransportKind::Http, addr);
+
+		// then
+		assert_eq!(rpc, Origin::Rpc("[::1]:8545".into()));
+		assert_eq!(http, Origin::Http("[::1]:8545".into()));
+	}
+
+	#[test]
+	fn should_compare_kind_of_origins() {
+		// given
+		let ws1 = Origin::Ws(1.into());
+		let ws2 = Origin::Ws(2.into());
+		let ipc = Origin::Ipc(1.into());
+
+		// then
+		assert!(ws1.same_kind(&ws2));
+		assert!(ws1 != ws2);
+		assert!(!ws1.same_kind(&ipc));
+		assert!(Origin::Unknown(None).same_kind(&Origin::Unknown(None)));
+	}
+
+	#[test]
+	fn should_deserialize_legacy_origin() {
+		// given
+		let legacy = r#""signer""#;
+		let full = r#"{"signer":"0x000000000000000000000000000000000000000000000000000000000000000a"}"#;
+
+		// when
+		let legacy: Origin = serde_json::from_str(legacy).unwrap();
+		let full: Origin = serde_json::from_str(full).unwrap();
+
+		// then
+		assert_eq!(legacy, Origin::Signer(H256::default()));
+		assert_eq!(full, Origin::Signer(10.into()));
+		assert_eq!(serde_json::from_str::<Origin>(r#""rpc""#).unwrap(), Origin::Rpc("".into()));
+		assert!(serde_json::from_str::<Origin>(r#""foo""#).is_err());
+
+		for origin in vec![Origin::Rpc(RpcOrigin::new("service", 1)), Origin::Http("localhost".into()), Origin::Ws(5.into()), Origin::Unknown(None)] {
+			let serialized = serde_json::to_string(&origin).unwrap();
+			assert_eq!(serde_json::from_str::<Origin>(&serialized).unwrap(), origin);
+		}
+	}
+
+	#[test]
+	fn should_classify_trusted_origins() {
+		assert!(Origin::Signer(1.into()).is_trusted());
+		assert!(Origin::Ipc(1.into()).is_trusted());
+		assert!(!Origin::Ws(1.into()).is_trusted());
+		assert!(!Origin::PubSub(1.into()).is_trusted());
+		assert!(!Origin::Rpc("rpc".into()).is_trusted());
+		assert!(!Origin::Http("localhost".into()).is_trusted());
+		assert!(!Origin::Dapps("dapp".into()).is_trusted());
+		assert!(!Origin::Unknown(None).is_trusted());
 	}
 
 	#[test]
@@ -158,4 +1315,85 @@ mod tests {
 		// then
 		assert_eq!(res, DappId("testapp".into()));
 	}
+
+	#[test]
+	fn should_reject_empty_dapp_id() {
+		assert_eq!(DappId::parse(""), Err(DappIdError::Empty));
+	}
+
+	#[test]
+	fn should_reject_overlong_dapp_id() {
+		// given
+		let id = "a".repeat(MAX_DAPP_ID_LEN + 1);
+
+		// when
+		let res = DappId::parse(&id);
+
+		// then
+		assert_eq!(res, Err(DappIdError::TooLong(MAX_DAPP_ID_LEN + 1, MAX_DAPP_ID_LEN)));
+		assert_eq!(DappId::parse_with_max_len("abc", 2), Err(DappIdError::TooLong(3, 2)));
+	}
+
+	#[test]
+	fn should_reject_dapp_id_with_control_characters() {
+		assert_eq!(DappId::parse("test\napp"), Err(DappIdError::ControlCharacter));
+	}
+
+	#[test]
+	fn should_compare_normalized_dapp_ids() {
+		// given
+		let upper = DappId("HTTP://Parity.IO/Path".into());
+		let lower = DappId("http://parity.io/Path".into());
+
+		// then
+		assert!(upper != lower);
+		assert_eq!(upper.normalized(), lower);
+		assert_eq!(upper.cmp_normalized(&lower), Ordering::Equal);
+		assert!(upper.cmp(&lower) != Ordering::Equal);
+		assert_eq!(DappId("HTTP://Parity.IO".into()).normalized(), DappId::from("http://parity.io"));
+		assert_eq!(DappId("a".into()).cmp_normalized(&DappId("B".into())), "a".cmp("B"));
+	}
+
+	#[test]
+	fn should_compute_stable_hash_of_dapp_id() {
+		// given
+		let expected: H256 = serde_json::from_str(r#""0x66ee6b0f3723d42a64611c75844cb395f19214bb2b0be313cebef43dd7cc0ad6""#).unwrap();
+
+		// then
+		assert_eq!(DappId::from("http://parity.io").stable_hash(), expected);
+		assert_eq!(DappId("HTTP://Parity.IO".into()).stable_hash(), expected);
+		assert!(DappId::from("http://parity.io/wallet").stable_hash() != expected);
+	}
+
+	#[test]
+	fn should_match_dapp_id_against_patterns() {
+		// given
+		let id = DappId::from("https://wallet.parity.io/#/accounts");
+		let with_port = DappId::from("http://wallet.parity.io:8080");
+		let plain = DappId::from("wallet.parity.io");
+
+		// then
+		assert!(id.matches("https://wallet.parity.io/#/accounts"));
+		assert!(plain.matches("wallet.parity.io"));
+		assert!(id.matches("*.parity.io"));
+		assert!(id.matches("*.Parity.io"));
+		assert!(with_port.matches("*.parity.io"));
+		assert!(!id.matches("*.ethereum.org"));
+		assert!(!id.matches("https://wallet.parity.io"));
+		assert!(!DappId::from("https://parity.io").matches("*.parity.io"));
+		assert!(!DappId::from("https://evilparity.io").matches("*.parity.io"));
+		assert!(!plain.matches("*.parity.io"));
+	}
+
+	#[test]
+	fn should_parse_and_normalize_url_dapp_id() {
+		// when
+		let res = DappId::parse("HTTPS://Wallet.Ethereum.org/Path/To/App");
+
+		// then
+		assert_eq!(res, Ok(DappId("https://wallet.ethereum.org/Path/To/App".into())));
+		assert_eq!(DappId::from("HTTP://Parity.io"), DappId("http://parity.io".into()));
+		assert_eq!(DappId::from("TestApp"), DappId("TestApp".into()));
+		assert_eq!(DappId::from(EthDappId::from("HTTP://Parity.io")), DappId("http://parity.io".into()));
+	}
 }
@@ -29,7 +29,8 @@ use tokio_core::reactor::{Handle, Remote, Interval};
 use tokio_core::net::{TcpListener, TcpStream};
 use ethkey::{Public, Secret, KeyPair, Signature, Random, Generator};
 use key_server_cluster::{Error, NodeId, SessionId, AclStorage, KeyStorage, DocumentEncryptedKeyShadow};
-use key_server_cluster::message::{self, Message, ClusterMessage, EncryptionMessage, DecryptionMessage};
+use key_server_cluster::message::{self, Message, ClusterMessage, EncryptionMessage, DecryptionMessage, Retransmit, SentMessages,
+	ScopedSessionId, SessionKind};
 use key_server_cluster::decryption_session::{SessionImpl as DecryptionSessionImpl, SessionState as DecryptionSessionState,
 	SessionParams as DecryptionSessionParams, Session as DecryptionSession, DecryptionSessionId};
 use key_server_cluster::encryption_session::{SessionImpl as EncryptionSessionImpl, SessionState as EncryptionSessionState,
@@ -56,6 +57,12 @@ const KEEP_ALIVE_DISCONNECT_INTERVAL: u64 = 60;
 /// session messages.
 const ENCRYPTION_SESSION_TIMEOUT_INTERVAL: u64 = 60;
 
+/// Every participant of active encryption session broadcasts SessionKeepAlive message every MAINTAIN_INTERVAL seconds.
+/// When no SessionKeepAlive messages have been received from participant within ENCRYPTION_SESSION_KEEP_ALIVE_TIMEOUT_INTERVAL
+/// seconds, we must treat this participant as dead && evict it. All nodes are required for encryption session
+/// => the session is finished with an error.
+const ENCRYPTION_SESSION_KEEP_ALIVE_TIMEOUT_INTERVAL: u64 = 30;
+
 /// When there are no decryption session-related messages for DECRYPTION_SESSION_TIMEOUT_INTERVAL seconds,
 /// we must treat this session as stalled && finish it with an error.
 /// This timeout is for cases when node is responding to KeepAlive messages, but intentionally ignores
@@ -170,6 +177,8 @@ pub struct ClusterConnections {
 pub struct ClusterSessions {
 	/// Self node id.
 	pub self_node_id: NodeId,
+	/// Self node secret key.
+	self_secret: Secret,
 	/// All nodes ids.
 	pub nodes: BTreeSet<NodeId>,
 	/// Reference to key storage
@@ -182,6 +191,8 @@ pub struct ClusterSessions {
 	pub decryption_sessions: RwLock<BTreeMap<DecryptionSessionId, QueuedDecryptionSession>>,
 	/// Make faulty encryption sessions.
 	pub make_faulty_encryption_sessions: AtomicBool,
+	/// Session messages, sent by this node.
+	pub sent_messages: Mutex<SentMessages>,
 }
 
 /// Encryption session and its message queue.
@@ -192,6 +203,10 @@ pub struct QueuedEncryptionSession {
 	pub cluster_view: Arc<ClusterView>,
 	/// Last received message time.
 	pub last_message_time: time::Instant,
+	/// Sequence number of the last keep alive message, sent by this node.
+	pub keep_alive_seq: u64,
+	/// Sequence number and receive time of the last keep alive message from every other session participant.
+	pub nodes_keep_alive: BTreeMap<NodeId, (u64, time::Instant)>,
 	/// Encryption session.
 	pub session: Arc<EncryptionSessionImpl>,
 	/// Messages queue.
@@ -377,6 +392,7 @@ impl ClusterCore {
 		trace!(target: "secretstore_net", "{}: executing maintain procedures", data.self_key_pair.public());
 
 		ClusterCore::keep_alive(data.clone());
+		data.sessions.keep_alive_encryption_sessions();
 		ClusterCore::connect_disconnected_nodes(data.clone());
 		data.sessions.stop_stalled_sessions();
 	}
@@ -418,7 +434,7 @@ impl ClusterCore {
 				data.sessions.on_connection_timeout(connection.node_id());
 			}
 			else if last_message_diff > time::Duration::from_secs(KEEP_ALIVE_SEND_INTERVAL) {
-				data.spawn(connection.send_message(Message::Cluster(ClusterMessage::KeepAlive(message::KeepAlive {}))));
+				data.spawn(connection.send_message(Message::keep_alive()));
 			}
 		}
 	}
@@ -460,10 +476,35 @@ impl ClusterCore {
 	fn process_connection_message(data: Arc<ClusterData>, connection: Arc<Connection>, message: Message) {
 		connection.set_last_message_time(time::Instant::now());
 		trace!(target: "secretstore_net", "{}: received message {} from {}", data.self_key_pair.public(), message, connection.node_id());
+		if let Err(err) = message.validate_size(data.sessions.nodes.len()) {
+			warn!(target: "secretstore_net", "{}: oversized message {} from node {}: {}", data.self_key_pair.public(), message, connection.node_id(), err);
+			return;
+		}
+
 		match message {
 			Message::Encryption(message) => ClusterCore::process_encryption_message(data, connection, message),
 			Message::Decryption(message) => ClusterCore::process_decryption_message(data, connection, message),
 			Message::Cluster(message) => ClusterCore::process_cluster_message(data, connection, message),
+			Message::ShareAdd(message) => warn!(target: "secretstore_net", "{}: share add sessions are not supported, ignoring {} from node {}", data.self_key_pair.public(), message, connection.node_id()),
+			Message::ServersSetChange(message) => warn!(target: "secretstore_net", "{}: servers set change sessions are not supported, ignoring {} from node {}", data.self_key_pair.public(), message, connection.node_id()),
+			Message::Batch(batch) => match batch.validate() {
+				Ok(_) => for message in batch.messages {
+					ClusterCore::process_connection_message(data.clone(), connection.clone(), message);
+				},
+				Err(err) => warn!(target: "secretstore_net", "{}: invalid message batch from node {}: {}", data.self_key_pair.public(), connection.node_id(), err),
+			},
+			Message::Retransmit(message) => ClusterCore::process_retransmit_message(data, connection, message),
+		}
+	}
+
+	/// Process request to resend the lost message.
+	fn process_retransmit_message(data: Arc<ClusterData>, connection: Arc<Connection>, message: Retransmit) {
+		let sent_message = data.sessions.sent_messages.lock()
+			.lookup(data.self_key_pair.public(), connection.node_id(), &message)
+			.cloned();
+		match sent_message {
+			Some(sent_message) => data.spawn(connection.send_message(sent_message)),
+			None => warn!(target: "secretstore_net", "{}: unable to process {} from node {}: message is not found", data.self_key_pair.public(), Message::Retransmit(message), connection.node_id()),
 		}
 	}
 
@@ -502,6 +543,12 @@ impl ClusterCore {
 					session.on_session_error(sender.clone(), message),
 				EncryptionMessage::SessionCompleted(ref message) => 
 					session.on_session_completed(sender.clone(), message),
+				EncryptionMessage::SessionKeepAlive(ref message) =>
+					session.on_session_keep_alive(sender.clone(), message)
+						.map(|_| data.sessions.on_encryption_session_keep_alive(&session_id, &sender, message.seq)),
+				// blame phase is not yet run by encryption session
+				EncryptionMessage::Complaint(_) | EncryptionMessage::ComplaintResponse(_) =>
+					Err(Error::InvalidMessage),
 			}) {
 				Ok(_) => {
 					// if session is completed => stop
@@ -531,10 +578,8 @@ impl ClusterCore {
 				},
 				Err(err) => {
 					warn!(target: "secretstore_net", "{}: encryption session error {} when processing message {} from node {}", data.self_key_pair.public(), err, message, sender);
-					data.sessions.respond_with_encryption_error(&session_id, message::SessionError {
-						session: session_id.clone().into(),
-						error: format!("{:?}", err),
-					});
+					let session_nonce = session.as_ref().ok().and_then(|session| session.nonce()).unwrap_or_default();
+					data.sessions.respond_with_encryption_error(&session_id, message::SessionError::new(&session_id, &session_nonce, &err));
 					if err != Error::InvalidSessionId {
 						data.sessions.remove_encryption_session(&session_id);
 					}
@@ -624,7 +669,7 @@ impl ClusterCore {
 	/// Process single cluster message from the connection.
 	fn process_cluster_message(data: Arc<ClusterData>, connection: Arc<Connection>, message: ClusterMessage) {
 		match message {
-			ClusterMessage::KeepAlive(_) => data.spawn(connection.send_message(Message::Cluster(ClusterMessage::KeepAliveResponse(message::KeepAliveResponse {})))),
+			ClusterMessage::KeepAlive(_) => data.spawn(connection.send_message(Message::keep_alive_response())),
 			ClusterMessage::KeepAliveResponse(_) => (),
 			_ => warn!(target: "secretstore_net", "{}: received unexpected message {} from node {} at {}", data.self_key_pair.public(), message, connection.node_id(), connection.node_address()),
 		}
@@ -706,12 +751,14 @@ impl ClusterSessions {
 	pub fn new(config: &ClusterConfiguration) -> Self {
 		ClusterSessions {
 			self_node_id: config.self_key_pair.public().clone(),
+			self_secret: config.self_key_pair.secret().clone(),
 			nodes: config.nodes.keys().cloned().collect(),
 			acl_storage: config.acl_storage.clone(),
 			key_storage: config.key_storage.clone(),
 			encryption_sessions: RwLock::new(BTreeMap::new()),
 			decryption_sessions: RwLock::new(BTreeMap::new()),
 			make_faulty_encryption_sessions: AtomicBool::new(false),
+			sent_messages: Mutex::new(SentMessages::new()),
 		}
 	}
 
@@ -735,13 +782,20 @@ impl ClusterSessions {
 		let session = Arc::new(EncryptionSessionImpl::new(EncryptionSessionParams {
 			id: session_id.clone(),
 			self_node_id: self.self_node_id.clone(),
+			self_secret: self.self_secret.clone(),
 			key_storage: self.key_storage.clone(),
 			cluster: cluster.clone(),
 		}));
+		let nodes_keep_alive = cluster.nodes().into_iter()
+			.filter(|node| node != &self.self_node_id)
+			.map(|node| (node, (0, time::Instant::now())))
+			.collect();
 		let encryption_session = QueuedEncryptionSession {
 			master: master,
 			cluster_view: cluster,
 			last_message_time: time::Instant::now(),
+			keep_alive_seq: 0,
+			nodes_keep_alive: nodes_keep_alive,
 			session: session.clone(),
 			queue: VecDeque::new()
 		};
@@ -754,6 +808,7 @@ impl ClusterSessions {
 
 	pub fn remove_encryption_session(&self, session_id: &SessionId) {
 		self.encryption_sessions.write().remove(session_id);
+		self.sent_messages.lock().remove_session(&ScopedSessionId::new(SessionKind::Encryption, session_id.clone()));
 	}
 
 	pub fn encryption_session(&self, session_id: &SessionId) -> Option<Arc<EncryptionSessionImpl>> {
@@ -771,6 +826,29 @@ impl ClusterSessions {
 			.and_then(|session| session.queue.pop_front())
 	}
 
+	pub fn on_encryption_session_keep_alive(&self, session_id: &SessionId, sender: &NodeId, seq: u64) {
+		if let Some(session) = self.encryption_sessions.write().get_mut(session_id) {
+			// ignore replayed keep alive messages && messages from non-participants
+			let is_fresh = session.nodes_keep_alive.get(sender).map_or(false, |&(last_seq, _)| last_seq < seq);
+			if is_fresh {
+				let now = time::Instant::now();
+				session.nodes_keep_alive.insert(sender.clone(), (seq, now));
+			}
+		}
+	}
+
+	pub fn keep_alive_encryption_sessions(&self) {
+		for (sid, session) in self.encryption_sessions.write().iter_mut() {
+			// session nonce is unknown until session is initialized
+			if let Some(nonce) = session.session.nonce() {
+				session.keep_alive_seq += 1;
+
+				// do not bother processing send error, as participant, which has not received keep alive, will be evicted
+				let _ = session.cluster_view.broadcast(Message::session_keep_alive(sid, &nonce, session.keep_alive_seq));
+			}
+		}
+	}
+
 	pub fn respond_with_encryption_error(&self, session_id: &SessionId, error: message::SessionError) {
 		self.encryption_sessions.read().get(session_id)
 			.map(|s| {
@@ -823,6 +901,7 @@ impl ClusterSessions {
 	}
 
 	pub fn remove_decryption_session(&self, session_id: &SessionId, sub_session_id: &Secret) {
+		self.sent_messages.lock().remove_session(&ScopedSessionId::decryption(session_id.clone(), sub_session_id.clone()));
 		let session_id = DecryptionSessionId::new(session_id.clone(), sub_session_id.clone());
 		self.decryption_sessions.write().remove(&session_id);
 	}
@@ -863,32 +942,51 @@ impl ClusterSessions {
 	}
 
 	fn stop_stalled_sessions(&self) {
+		// sessions are removed after the lock is released, as removal locks the sessions again
+		let mut finished_encryption_sessions = Vec::new();
 		{
-			let sessions = self.encryption_sessions.write();
-			for sid in sessions.keys().collect::<Vec<_>>() {
-				let session = sessions.get(&sid).expect("enumerating only existing sessions; qed");
-				if time::Instant::now() - session.last_message_time > time::Duration::from_secs(ENCRYPTION_SESSION_TIMEOUT_INTERVAL) {
+			let sessions = self.encryption_sessions.read();
+			for (sid, session) in sessions.iter() {
+				let now = time::Instant::now();
+				let dead_node = session.nodes_keep_alive.iter()
+					.find(|&(_, &(_, last_keep_alive))| now - last_keep_alive > time::Duration::from_secs(ENCRYPTION_SESSION_KEEP_ALIVE_TIMEOUT_INTERVAL))
+					.map(|(node, _)| node);
+				let is_stalled = if now - session.last_message_time > time::Duration::from_secs(ENCRYPTION_SESSION_TIMEOUT_INTERVAL) {
 					session.session.on_session_timeout();
-					if session.session.state() == EncryptionSessionState::Finished
-						|| session.session.state() == EncryptionSessionState::Failed {
-						self.remove_encryption_session(&sid);
-					}
+					true
+				} else if let Some(dead_node) = dead_node {
+					session.session.on_node_timeout(dead_node);
+					true
+				} else {
+					false
+				};
+
+				if is_stalled && (session.session.state() == EncryptionSessionState::Finished
+					|| session.session.state() == EncryptionSessionState::Failed) {
+					finished_encryption_sessions.push(sid.clone());
 				}
 			}
 		}
+		for sid in finished_encryption_sessions {
+			self.remove_encryption_session(&sid);
+		}
+
+		let mut finished_decryption_sessions = Vec::new();
 		{
-			let sessions = self.decryption_sessions.write();
-			for sid in sessions.keys().collect::<Vec<_>>() {
-				let session = sessions.get(&sid).expect("enumerating only existing sessions; qed");
+			let sessions = self.decryption_sessions.read();
+			for (sid, session) in sessions.iter() {
 				if time::Instant::now() - session.last_message_time > time::Duration::from_secs(DECRYPTION_SESSION_TIMEOUT_INTERVAL) {
 					session.session.on_session_timeout();
 					if session.session.state() == DecryptionSessionState::Finished
 						|| session.session.state() == DecryptionSessionState::Failed {
-						self.remove_decryption_session(&sid.id, &sid.access_key);
+						finished_decryption_sessions.push(sid.clone());
 					}
 				}
 			}
 		}
+		for sid in finished_decryption_sessions {
+			self.remove_decryption_session(&sid.id, &sid.access_key);
+		}
 	}
 
 	pub fn on_connection_timeout(&self, node_id: &NodeId) {
@@ -1001,6 +1099,7 @@ impl Cluster for ClusterView {
 		for node in core.nodes.iter().filter(|n| *n != core.cluster.self_key_pair.public()) {
 			trace!(target: "secretstore_net", "{}: sent message {} to {}", core.cluster.self_key_pair.public(), message, node);
 			let connection = core.cluster.connection(node).ok_or(Error::NodeDisconnected)?;
+			core.cluster.sessions.sent_messages.lock().record(node, &message);
 			core.cluster.spawn(connection.send_message(message.clone()))
 		}
 		Ok(())
@@ -1010,6 +1109,7 @@ impl Cluster for ClusterView {
 		let core = self.core.lock();
 		trace!(target: "secretstore_net", "{}: sent message {} to {}", core.cluster.self_key_pair.public(), message, to);
 		let connection = core.cluster.connection(to).ok_or(Error::NodeDisconnected)?;
+		core.cluster.sessions.sent_messages.lock().record(to, &message);
 		core.cluster.spawn(connection.send_message(message));
 		Ok(())
 	}
@@ -1308,4 +1408,27 @@ pub mod tests {
 			}
 		}
 	}
+
+	#[test]
+	fn encryption_session_with_only_keep_alive_traffic_is_timed_out() {
+		let mut core = Core::new().unwrap();
+		let clusters = make_clusters(&core, 6025, 3);
+		run_clusters(&clusters);
+		loop_until(&mut core, time::Duration::from_millis(300), || clusters.iter().all(all_connections_established));
+
+		// start session && pretend that the last session message was received long ago
+		let session = clusters[0].client().new_encryption_session(SessionId::default(), 1).unwrap();
+		let sessions = &clusters[0].data.sessions;
+		sessions.encryption_sessions.write().get_mut(&SessionId::default()).unwrap().last_message_time =
+			time::Instant::now() - time::Duration::from_secs(super::ENCRYPTION_SESSION_TIMEOUT_INTERVAL + 1);
+
+		// other nodes are still alive, but keep alive messages do not postpone session timeout
+		for i in 1..3 {
+			sessions.on_encryption_session_keep_alive(&SessionId::default(), &clusters[i].data.sessions.self_node_id, 1);
+		}
+		sessions.stop_stalled_sessions();
+
+		assert_eq!(session.state(), EncryptionSessionState::Failed);
+		assert!(clusters[0].client().encryption_session(&SessionId::default()).is_none());
+	}
 }
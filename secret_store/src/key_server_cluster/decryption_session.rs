@@ -24,7 +24,7 @@ use ethkey::{self, Secret, Public, Signature};
 use key_server_cluster::{Error, AclStorage, DocumentKeyShare, NodeId, SessionId, DocumentEncryptedKeyShadow};
 use key_server_cluster::cluster::Cluster;
 use key_server_cluster::math;
-use key_server_cluster::message::{Message, DecryptionMessage, InitializeDecryptionSession, ConfirmDecryptionInitialization,
+use key_server_cluster::message::{Message, InitializeDecryptionSession, ConfirmDecryptionInitialization,
 	RequestPartialDecryption, PartialDecryption, DecryptionSessionError, DecryptionSessionCompleted};
 
 /// Decryption session API.
@@ -222,12 +222,8 @@ impl SessionImpl {
 			// not enough nodes => pass initialization message to all other nodes
 			SessionState::WaitingForInitializationConfirm => {
 				for node in self.encrypted_data.id_numbers.keys().filter(|n| *n != self.node()) {
-					self.cluster.send(node, Message::Decryption(DecryptionMessage::InitializeDecryptionSession(InitializeDecryptionSession {
-							session: self.id.clone().into(),
-							sub_session: self.access_key.clone().into(),
-							requestor_signature: requestor_signature.clone().into(),
-							is_shadow_decryption: is_shadow_decryption,
-						})))?;
+					self.cluster.send(node, Message::initialize_decryption_session(&self.id, &self.access_key,
+						&requestor_signature, is_shadow_decryption))?;
 				}
 			},
 			// we can decrypt data on our own
@@ -271,11 +267,7 @@ impl SessionImpl {
 
 		// respond to master node
 		data.master = Some(sender.clone());
-		self.cluster.send(&sender, Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(ConfirmDecryptionInitialization {
-			session: self.id.clone().into(),
-			sub_session: self.access_key.clone().into(),
-			is_confirmed: is_requestor_allowed_to_read,
-		})))
+		self.cluster.send(&sender, Message::confirm_decryption_initialization(&self.id, &self.access_key, is_requestor_allowed_to_read))
 	}
 
 	/// When session initialization confirmation message is reeived.
@@ -349,12 +341,8 @@ impl SessionImpl {
 			let nodes = message.nodes.iter().cloned().map(Into::into).collect();
 			do_partial_decryption(self.node(), &requestor, is_shadow_decryption, &nodes, &self.access_key, &self.encrypted_data)?
 		};
-		self.cluster.send(&sender, Message::Decryption(DecryptionMessage::PartialDecryption(PartialDecryption {
-			session: self.id.clone().into(),
-			sub_session: self.access_key.clone().into(),
-			shadow_point: decryption_result.shadow_point.into(),
-			decrypt_shadow: decryption_result.decrypt_shadow,
-		})))?;
+		self.cluster.send(&sender, Message::partial_decryption(&self.id, &self.access_key,
+			decryption_result.shadow_point, decryption_result.decrypt_shadow))?;
 
 		// master could ask us for another partial decryption in case of restart
 		// => no state change is required
@@ -389,10 +377,7 @@ impl SessionImpl {
 		}
 
 		// notify all other nodes about session completion
-		self.cluster.broadcast(Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(DecryptionSessionCompleted {
-			session: self.id.clone().into(),
-			sub_session: self.access_key.clone().into(),
-		})))?;
+		self.cluster.broadcast(Message::decryption_session_completed(&self.id, &self.access_key))?;
 
 		// do decryption
 		SessionImpl::do_decryption(self.access_key.clone(), &self.encrypted_data, &mut *data)?;
@@ -551,11 +536,7 @@ impl SessionImpl {
 		data.shadow_points.clear();
 		for node in confirmed_nodes.iter().filter(|n| n != &&self_node_id) {
 			data.shadow_requests.insert(node.clone());
-			cluster.send(node, Message::Decryption(DecryptionMessage::RequestPartialDecryption(RequestPartialDecryption {
-				session: session_id.clone().into(),
-				sub_session: access_key.clone().into(),
-				nodes: confirmed_nodes.iter().cloned().map(Into::into).collect(),
-			})))?;
+			cluster.send(node, Message::request_partial_decryption(&session_id, &access_key, &confirmed_nodes))?;
 		}
 
 		if data.confirmed_nodes.remove(&self_node_id) {
@@ -20,11 +20,12 @@ use std::time;
 use std::sync::Arc;
 use parking_lot::{Condvar, Mutex};
 use ethkey::{Public, Secret};
-use key_server_cluster::{Error, NodeId, SessionId, KeyStorage, DocumentKeyShare};
+use util::H256;
+use key_server_cluster::{Error, NodeId, SessionId, KeyStorage, DocumentKeyShare, SerializableH256};
 use key_server_cluster::math;
 use key_server_cluster::cluster::Cluster;
-use key_server_cluster::message::{Message, EncryptionMessage, InitializeSession, ConfirmInitialization, CompleteInitialization,
-	KeysDissemination, PublicKeyShare, SessionError, SessionCompleted};
+use key_server_cluster::message::{Message, InitializeSession, ConfirmInitialization, CompleteInitialization,
+	AgreedParameters, KeysDissemination, PublicKeyShare, SessionError, SessionCompleted, SessionKeepAlive};
 
 /// Encryption session API.
 pub trait Session: Send + Sync + 'static {
@@ -52,6 +53,8 @@ pub struct SessionImpl {
 	id: SessionId,
 	/// Public identifier of this node.
 	self_node_id: NodeId,
+	/// Secret key of this node, used to decrypt keys, disseminated to this node.
+	self_secret: Secret,
 	/// Key storage.
 	key_storage: Arc<KeyStorage>,
 	/// Cluster which allows this node to send messages to other nodes in the cluster.
@@ -68,6 +71,8 @@ pub struct SessionParams {
 	pub id: SessionId,
 	/// Id of node, on which this session is running.
 	pub self_node_id: Public,
+	/// Secret key of this node, used to decrypt keys, disseminated to this node.
+	pub self_secret: Secret,
 	/// Key storage.
 	pub key_storage: Arc<KeyStorage>,
 	/// Cluster
@@ -85,11 +90,13 @@ struct SessionData {
 	// === Values, filled when session initialization just starts ===
 	/// Reference to the node, which has started this session.
 	master: Option<NodeId>,
+	/// Session nonce, generated by master node. Every session message must carry this nonce.
+	nonce: Option<H256>,
 
 	// === Values, filled when session initialization is completed ===
-	/// Threshold value for this DKG. Only `threshold + 1` will be able to collectively recreate joint secret,
+	/// Parameters of this DKG. Only `threshold + 1` will be able to collectively recreate joint secret,
 	/// and thus - decrypt message, encrypted with joint public.
-	threshold: Option<usize>,
+	parameters: Option<AgreedParameters>,
 	/// Random point, jointly generated by every node in the cluster.
 	derived_point: Option<Public>,
 	/// Nodes-specific data.
@@ -185,6 +192,7 @@ impl SessionImpl {
 		SessionImpl {
 			id: params.id,
 			self_node_id: params.self_node_id,
+			self_secret: params.self_secret,
 			key_storage: params.key_storage,
 			cluster: params.cluster,
 			completed: Condvar::new(),
@@ -192,7 +200,8 @@ impl SessionImpl {
 				state: SessionState::WaitingForInitialization,
 				simulate_faulty_behaviour: false,
 				master: None,
-				threshold: None,
+				nonce: None,
+				parameters: None,
 				derived_point: None,
 				nodes: BTreeMap::new(),
 				secret_coeff: None,
@@ -214,6 +223,11 @@ impl SessionImpl {
 		self.data.lock().derived_point.clone()
 	}
 
+	/// Get session nonce (if it is known).
+	pub fn nonce(&self) -> Option<H256> {
+		self.data.lock().nonce.clone()
+	}
+
 	/// Simulate faulty encryption session behaviour.
 	pub fn simulate_faulty_behaviour(&self) {
 		self.data.lock().simulate_faulty_behaviour = true;
@@ -233,7 +247,8 @@ impl SessionImpl {
 
 		// update state
 		data.master = Some(self.node().clone());
-		data.threshold = Some(threshold);
+		data.nonce = Some(H256::random());
+		data.parameters = Some(AgreedParameters::with_threshold(threshold));
 		for node_id in &nodes {
 			// generate node identification parameter
 			let node_id_number = math::generate_random_scalar()?;
@@ -247,10 +262,7 @@ impl SessionImpl {
 				data.state = SessionState::WaitingForInitializationConfirm(visit_policy);
 
 				// start initialization
-				self.cluster.send(&next_node, Message::Encryption(EncryptionMessage::InitializeSession(InitializeSession {
-						session: self.id.clone().into(),
-						derived_point: derived_point.into(),
-					})))
+				self.cluster.send(&next_node, Message::initialize_session(&self.id, &data.session_nonce(), &derived_point))
 			},
 			None => {
 				drop(data);
@@ -279,13 +291,11 @@ impl SessionImpl {
 		math::update_random_point(&mut derived_point)?;
 
 		// send confirmation back to master node
-		self.cluster.send(&sender, Message::Encryption(EncryptionMessage::ConfirmInitialization(ConfirmInitialization {
-			session: self.id.clone().into(),
-			derived_point: derived_point.into(),
-		})))?;
+		self.cluster.send(&sender, Message::confirm_initialization(&self.id, &message.session_nonce, &derived_point))?;
 
 		// update state
 		data.master = Some(sender);
+		data.nonce = Some(message.session_nonce.clone().into());
 		data.state = SessionState::WaitingForInitializationComplete;
 
 		Ok(())
@@ -298,6 +308,7 @@ impl SessionImpl {
 
 		let mut data = self.data.lock();
 		debug_assert!(data.nodes.contains_key(&sender));
+		check_nonce(&data, &message.session_nonce)?;
 
 		// check state && select new node to be initialized
 		let next_receiver = match data.state {
@@ -313,10 +324,7 @@ impl SessionImpl {
 
 		// proceed message
 		if let Some(next_receiver) = next_receiver {
-			return self.cluster.send(&next_receiver, Message::Encryption(EncryptionMessage::InitializeSession(InitializeSession {
-					session: self.id.clone().into(),
-					derived_point: message.derived_point.clone().into(),
-				})));
+			return self.cluster.send(&next_receiver, Message::initialize_session(&self.id, &message.session_nonce, &message.derived_point));
 		}
 
 		// now it is time for keys dissemination (KD) phase
@@ -334,6 +342,7 @@ impl SessionImpl {
 		let nodes_ids = message.nodes.keys().cloned().map(Into::into).collect();
 		check_cluster_nodes(self.node(), &nodes_ids)?;
 		check_threshold(message.threshold, &nodes_ids)?;
+		message.validate_ids()?;
 
 		let mut data = self.data.lock();
 
@@ -344,9 +353,10 @@ impl SessionImpl {
 		if data.master != Some(sender) {
 			return Err(Error::InvalidMessage);
 		}
+		check_nonce(&data, &message.session_nonce)?;
 
 		// remember passed data
-		data.threshold = Some(message.threshold);
+		data.parameters = Some(AgreedParameters::new(message));
 		data.derived_point = Some(message.derived_point.clone().into());
 		data.nodes = message.nodes.iter().map(|(id, number)| (id.clone().into(), NodeData::with_id_number(number.clone().into()))).collect();
 
@@ -375,12 +385,12 @@ impl SessionImpl {
 			}
 		}
 		debug_assert!(data.nodes.contains_key(&sender));
+		check_nonce(&data, &message.session_nonce)?;
 
 		// check message
-		let threshold = data.threshold.expect("threshold is filled in initialization phase; KD phase follows initialization phase; qed");
-		if message.publics.len() != threshold + 1 {
-			return Err(Error::InvalidMessage);
-		}
+		data.parameters.as_ref()
+			.expect("parameters are filled in initialization phase; KD phase follows initialization phase; qed")
+			.validate_keys_dissemination(message)?;
 
 		// update node data
 		{
@@ -389,8 +399,9 @@ impl SessionImpl {
 				return Err(Error::InvalidStateForRequest);
 			}
 
-			node_data.secret1 = Some(message.secret1.clone().into());
-			node_data.secret2 = Some(message.secret2.clone().into());
+			let (secret1, secret2) = message.decrypt_secrets(&self.self_secret)?;
+			node_data.secret1 = Some(secret1);
+			node_data.secret2 = Some(secret2);
 			node_data.publics = Some(message.publics.iter().cloned().map(Into::into).collect());
 		}
 
@@ -415,6 +426,7 @@ impl SessionImpl {
 				_ => return Err(Error::InvalidStateForRequest),
 			}
 		}
+		check_nonce(&data, &message.session_nonce)?;
 
 		// update node data with received public share
 		{
@@ -450,6 +462,7 @@ impl SessionImpl {
 				_ => return Err(Error::InvalidStateForRequest),
 			}
 		}
+		check_nonce(&data, &message.session_nonce)?;
 
 		// if we are not masters, save result and respond with confirmation
 		if data.master.as_ref() != Some(self.node()) {
@@ -460,7 +473,7 @@ impl SessionImpl {
 
 			// save encrypted data to key storage
 			let encrypted_data = DocumentKeyShare {
-				threshold: data.threshold.expect("threshold is filled in initialization phase; KG phase follows initialization phase; qed"),
+				threshold: data.parameters.as_ref().expect("parameters are filled in initialization phase; KG phase follows initialization phase; qed").threshold,
 				id_numbers: data.nodes.iter().map(|(node_id, node_data)| (node_id.clone(), node_data.id_number.clone())).collect(),
 				secret_share: data.secret_share.as_ref().expect("secret_share is filled in KG phase; we are at the end of KG phase; qed").clone(),
 				common_point: message.common_point.clone().into(),
@@ -471,11 +484,8 @@ impl SessionImpl {
 
 			// then respond with confirmation
 			data.state = SessionState::Finished;
-			return self.cluster.send(&sender, Message::Encryption(EncryptionMessage::SessionCompleted(SessionCompleted {
-				session: self.id.clone().into(),
-				common_point: encrypted_data.common_point.clone().into(),
-				encrypted_point: encrypted_data.encrypted_point.clone().into(),
-			})));
+			return self.cluster.send(&sender, Message::session_completed(&self.id, &message.session_nonce,
+				&encrypted_data.common_point, &encrypted_data.encrypted_point));
 		}
 
 		// remember that we have received confirmation from sender node
@@ -504,6 +514,11 @@ impl SessionImpl {
 	pub fn on_session_error(&self, sender: NodeId, message: &SessionError) -> Result<(), Error> {
 		let mut data = self.data.lock();
 
+		// error could be received before session nonce is known
+		if data.nonce.is_some() {
+			check_nonce(&data, &message.session_nonce)?;
+		}
+
 		warn!("{}: encryption session failed with error: {} from {}", self.node(), message.error, sender);
 
 		data.state = SessionState::Failed;
@@ -514,6 +529,11 @@ impl SessionImpl {
 		Ok(())
 	}
 
+	/// When session keep alive message is received.
+	pub fn on_session_keep_alive(&self, _sender: NodeId, message: &SessionKeepAlive) -> Result<(), Error> {
+		check_nonce(&self.data.lock(), &message.session_nonce)
+	}
+
 	/// When connection to one of cluster nodes has timeouted.
 	pub fn on_node_timeout(&self, node: &NodeId) {
 		let mut data = self.data.lock();
@@ -550,12 +570,9 @@ impl SessionImpl {
 		data.derived_point = Some(derived_point.clone().into());
 
 		// broadcast derived point && other session paraeters to every other node
-		self.cluster.broadcast(Message::Encryption(EncryptionMessage::CompleteInitialization(CompleteInitialization {
-			session: self.id.clone().into(),
-			nodes: data.nodes.iter().map(|(id, data)| (id.clone().into(), data.id_number.clone().into())).collect(),
-			threshold: data.threshold.expect("threshold is filled in initialization phase; KD phase follows initialization phase; qed"),
-			derived_point: derived_point.into(),
-		})))
+		let threshold = data.parameters.as_ref().expect("parameters are filled in initialization phase; KD phase follows initialization phase; qed").threshold;
+		let nodes = data.nodes.iter().map(|(id, data)| (id.clone(), data.id_number.clone())).collect();
+		self.cluster.broadcast(Message::complete_initialization(&self.id, &data.session_nonce(), nodes, threshold, derived_point)?)
 	}
 
 	/// Keys dissemination (KD) phase
@@ -563,7 +580,7 @@ impl SessionImpl {
 		let mut data = self.data.lock();
 
 		// pick 2t + 2 random numbers as polynomial coefficients for 2 polynoms
-		let threshold = data.threshold.expect("threshold is filled on initialization phase; KD phase follows initialization phase; qed");
+		let threshold = data.parameters.as_ref().expect("parameters are filled on initialization phase; KD phase follows initialization phase; qed").threshold;
 		let polynom1 = math::generate_random_polynom(threshold)?;
 		let polynom2 = math::generate_random_polynom(threshold)?;
 		data.secret_coeff = Some(polynom1[0].clone());
@@ -575,6 +592,7 @@ impl SessionImpl {
 			&polynom2)?;
 
 		// compute secret values for every other node
+		let session_nonce = data.session_nonce();
 		for (node, node_data) in data.nodes.iter_mut() {
 			let secret1 = math::compute_polynom(&polynom1, &node_data.id_number)?;
 			let secret2 = math::compute_polynom(&polynom2, &node_data.id_number)?;
@@ -584,12 +602,7 @@ impl SessionImpl {
 				node_data.secret1_sent = Some(secret1.clone());
 				node_data.secret2_sent = Some(secret2.clone());
 
-				self.cluster.send(&node, Message::Encryption(EncryptionMessage::KeysDissemination(KeysDissemination {
-					session: self.id.clone().into(),
-					secret1: secret1.into(),
-					secret2: secret2.into(),
-					publics: publics.iter().cloned().map(Into::into).collect(),
-				})))?;
+				self.cluster.send(&node, Message::keys_dissemination(&self.id, &session_nonce, node, &secret1, &secret2, &publics)?)?;
 			} else {
 				node_data.secret1 = Some(secret1);
 				node_data.secret2 = Some(secret2);
@@ -608,7 +621,7 @@ impl SessionImpl {
 		let mut data = self.data.lock();
 		
 		// key verification (KV) phase: check that other nodes have passed correct secrets
-		let threshold = data.threshold.expect("threshold is filled in initialization phase; KV phase follows initialization phase; qed");
+		let threshold = data.parameters.as_ref().expect("parameters are filled in initialization phase; KV phase follows initialization phase; qed").threshold;
 		let derived_point = data.derived_point.clone().expect("derived point generated on initialization phase; KV phase follows initialization phase; qed");
 		let number_id = data.nodes[self.node()].id_number.clone();
 		for (_	, node_data) in data.nodes.iter_mut().filter(|&(node_id, _)| node_id != self.node()) {
@@ -644,10 +657,7 @@ impl SessionImpl {
 		self_node.public_share = Some(self_public_share.clone());
 
 		// broadcast self public key share
-		self.cluster.broadcast(Message::Encryption(EncryptionMessage::PublicKeyShare(PublicKeyShare {
-			session: self.id.clone().into(),
-			public_share: self_public_share.into(),
-		})))
+		self.cluster.broadcast(Message::public_key_share(&self.id, &data.session_nonce(), &self_public_share))
 	}
 
 	/// Complete encryption
@@ -675,7 +685,7 @@ impl SessionImpl {
 
 		// then save encrypted data to the key storage
 		let encrypted_data = DocumentKeyShare {
-			threshold: data.threshold.expect("threshold is filled in initialization phase; KG phase follows initialization phase; qed"),
+			threshold: data.parameters.as_ref().expect("parameters are filled in initialization phase; KG phase follows initialization phase; qed").threshold,
 			id_numbers: data.nodes.iter().map(|(node_id, node_data)| (node_id.clone(), node_data.id_number.clone())).collect(),
 			secret_share: data.secret_share.as_ref().expect("secret_share is filled in KG phase; we are at the end of KG phase; qed").clone(),
 			common_point: encrypted_secret_point.common_point,
@@ -685,11 +695,8 @@ impl SessionImpl {
 			.map_err(|e| Error::KeyStorage(e.into()))?;
 
 		// then distribute encrypted data to every other node
-		self.cluster.broadcast(Message::Encryption(EncryptionMessage::SessionCompleted(SessionCompleted {
-			session: self.id.clone().into(),
-			common_point: encrypted_data.common_point.clone().into(),
-			encrypted_point: encrypted_data.encrypted_point.clone().into(),
-		})))?;
+		self.cluster.broadcast(Message::session_completed(&self.id, &data.session_nonce(),
+			&encrypted_data.common_point, &encrypted_data.encrypted_point))?;
 
 		// then wait for confirmation from all other nodes
 		{
@@ -754,6 +761,13 @@ impl EveryOtherNodeVisitor {
 	}
 }
 
+impl SessionData {
+	/// Get session nonce to be sent in session messages.
+	fn session_nonce(&self) -> SerializableH256 {
+		self.nonce.clone().expect("nonce is filled when session initialization starts; messages are sent after initialization; qed").into()
+	}
+}
+
 impl NodeData {
 	fn with_id_number(node_id_number: Secret) -> Self {
 		NodeData {
@@ -775,6 +789,14 @@ impl Debug for SessionImpl {
 	}
 }
 
+/// Check that message has been sent within this session (and is not replayed from another one).
+fn check_nonce(data: &SessionData, nonce: &H256) -> Result<(), Error> {
+	match data.nonce {
+		Some(ref session_nonce) if session_nonce == nonce => Ok(()),
+		_ => Err(Error::InvalidMessage),
+	}
+}
+
 pub fn check_cluster_nodes(self_node_id: &NodeId, nodes: &BTreeSet<NodeId>) -> Result<(), Error> {
 	// at least two nodes must be in cluster
 	if nodes.len() < 1 {
@@ -803,6 +825,7 @@ mod tests {
 	use std::sync::Arc;
 	use std::collections::{BTreeSet, BTreeMap, VecDeque};
 	use tokio_core::reactor::Core;
+	use util::H256;
 	use ethkey::{Random, Generator};
 	use key_server_cluster::{NodeId, SessionId, Error, DummyKeyStorage};
 	use key_server_cluster::message::{self, Message, EncryptionMessage};
@@ -835,6 +858,7 @@ mod tests {
 				let session = SessionImpl::new(SessionParams {
 					id: session_id.clone(),
 					self_node_id: node_id.clone(),
+					self_secret: key_pair.secret().clone(),
 					key_storage: Arc::new(DummyKeyStorage::default()),
 					cluster: cluster.clone(),
 				});
@@ -924,6 +948,7 @@ mod tests {
 		let session = SessionImpl::new(SessionParams {
 			id: SessionId::default(),
 			self_node_id: node_id.clone(),
+			self_secret: math::generate_random_scalar().unwrap(),
 			key_storage: Arc::new(DummyKeyStorage::default()),
 			cluster: cluster,
 		});
@@ -948,6 +973,7 @@ mod tests {
 		l.take_and_process_message().unwrap();
 		assert_eq!(l.first_slave().on_initialize_session(m, &message::InitializeSession {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			derived_point: math::generate_random_point().unwrap().into(),
 		}).unwrap_err(), Error::InvalidStateForRequest);
 	}
@@ -978,6 +1004,7 @@ mod tests {
 		l.take_and_process_message().unwrap();
 		assert_eq!(l.master().on_confirm_initialization(s, &message::ConfirmInitialization {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			derived_point: math::generate_random_point().unwrap().into(),
 		}).unwrap_err(), Error::InvalidStateForRequest);
 	}
@@ -989,10 +1016,31 @@ mod tests {
 		l.take_and_process_message().unwrap();
 		assert_eq!(l.master().on_confirm_initialization(s, &message::ConfirmInitialization {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			derived_point: math::generate_random_point().unwrap().into(),
 		}).unwrap_err(), Error::InvalidStateForRequest);
 	}
 
+	#[test]
+	fn slave_receives_session_nonce_on_initialization() {
+		let (_, _, _, mut l) = make_simple_cluster(0, 2).unwrap();
+		assert!(l.master().nonce().is_some());
+		assert!(l.first_slave().nonce().is_none());
+		l.take_and_process_message().unwrap();
+		assert_eq!(l.first_slave().nonce(), l.master().nonce());
+	}
+
+	#[test]
+	fn fails_to_accept_message_with_wrong_session_nonce() {
+		let (sid, _, s, mut l) = make_simple_cluster(0, 2).unwrap();
+		l.take_and_process_message().unwrap();
+		assert_eq!(l.master().on_confirm_initialization(s, &message::ConfirmInitialization {
+			session: sid.into(),
+			session_nonce: H256::random().into(),
+			derived_point: math::generate_random_point().unwrap().into(),
+		}).unwrap_err(), Error::InvalidMessage);
+	}
+
 	#[test]
 	fn master_updates_derived_point_on_initialization_completion() {
 		let (_, _, _, mut l) = make_simple_cluster(0, 2).unwrap();
@@ -1017,6 +1065,7 @@ mod tests {
 		nodes.insert(math::generate_random_point().unwrap(), math::generate_random_scalar().unwrap());
 		assert_eq!(l.first_slave().on_complete_initialization(m, &message::CompleteInitialization {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			nodes: nodes.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
 			threshold: 0,
 			derived_point: math::generate_random_point().unwrap().into(),
@@ -1031,6 +1080,7 @@ mod tests {
 		nodes.insert(s, math::generate_random_scalar().unwrap());
 		assert_eq!(l.first_slave().on_complete_initialization(m, &message::CompleteInitialization {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			nodes: nodes.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
 			threshold: 2,
 			derived_point: math::generate_random_point().unwrap().into(),
@@ -1045,6 +1095,7 @@ mod tests {
 		nodes.insert(s, math::generate_random_scalar().unwrap());
 		assert_eq!(l.first_slave().on_complete_initialization(m, &message::CompleteInitialization {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			nodes: nodes.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
 			threshold: 0,
 			derived_point: math::generate_random_point().unwrap().into(),
@@ -1064,6 +1115,7 @@ mod tests {
 		nodes.insert(l.second_slave().node().clone(), math::generate_random_scalar().unwrap());
 		assert_eq!(l.first_slave().on_complete_initialization(l.second_slave().node().clone(), &message::CompleteInitialization {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			nodes: nodes.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
 			threshold: 0,
 			derived_point: math::generate_random_point().unwrap().into(),
@@ -1073,12 +1125,10 @@ mod tests {
 	#[test]
 	fn fails_to_accept_keys_dissemination_if_not_waiting_for_it() {
 		let (sid, _, s, l) = make_simple_cluster(0, 2).unwrap();
-		assert_eq!(l.master().on_keys_dissemination(s, &message::KeysDissemination {
-			session: sid.into(),
-			secret1: math::generate_random_scalar().unwrap().into(),
-			secret2: math::generate_random_scalar().unwrap().into(),
-			publics: vec![math::generate_random_point().unwrap().into()],
-		}).unwrap_err(), Error::InvalidStateForRequest);
+		let message = message::KeysDissemination::new(&sid, &l.master().nonce().unwrap(), l.master().node(),
+			&math::generate_random_scalar().unwrap(), &math::generate_random_scalar().unwrap(),
+			&[math::generate_random_point().unwrap()]).unwrap();
+		assert_eq!(l.master().on_keys_dissemination(s, &message).unwrap_err(), Error::InvalidStateForRequest);
 	}
 
 	#[test]
@@ -1091,12 +1141,10 @@ mod tests {
 		l.take_and_process_message().unwrap(); // m -> s1: CompleteInitialization
 		l.take_and_process_message().unwrap(); // m -> s2: CompleteInitialization
 		l.take_and_process_message().unwrap(); // m -> s1: KeysDissemination
-		assert_eq!(l.first_slave().on_keys_dissemination(m, &message::KeysDissemination {
-			session: sid.into(),
-			secret1: math::generate_random_scalar().unwrap().into(),
-			secret2: math::generate_random_scalar().unwrap().into(),
-			publics: vec![math::generate_random_point().unwrap().into(), math::generate_random_point().unwrap().into()],
-		}).unwrap_err(), Error::InvalidMessage);
+		let message = message::KeysDissemination::new(&sid, &l.master().nonce().unwrap(), l.first_slave().node(),
+			&math::generate_random_scalar().unwrap(), &math::generate_random_scalar().unwrap(),
+			&[math::generate_random_point().unwrap(), math::generate_random_point().unwrap()]).unwrap();
+		assert_eq!(l.first_slave().on_keys_dissemination(m, &message).unwrap_err(), Error::InvalidMessage);
 	}
 
 	#[test]
@@ -1109,12 +1157,25 @@ mod tests {
 		l.take_and_process_message().unwrap(); // m -> s1: CompleteInitialization
 		l.take_and_process_message().unwrap(); // m -> s2: CompleteInitialization
 		l.take_and_process_message().unwrap(); // m -> s1: KeysDissemination
-		assert_eq!(l.first_slave().on_keys_dissemination(m, &message::KeysDissemination {
-			session: sid.into(),
-			secret1: math::generate_random_scalar().unwrap().into(),
-			secret2: math::generate_random_scalar().unwrap().into(),
-			publics: vec![math::generate_random_point().unwrap().into()],
-		}).unwrap_err(), Error::InvalidStateForRequest);
+		let message = message::KeysDissemination::new(&sid, &l.master().nonce().unwrap(), l.first_slave().node(),
+			&math::generate_random_scalar().unwrap(), &math::generate_random_scalar().unwrap(),
+			&[math::generate_random_point().unwrap()]).unwrap();
+		assert_eq!(l.first_slave().on_keys_dissemination(m, &message).unwrap_err(), Error::InvalidStateForRequest);
+	}
+
+	#[test]
+	fn fails_to_accept_keys_dissemination_encrypted_for_other_node() {
+		let (sid, m, _, mut l) = make_simple_cluster(0, 3).unwrap();
+		l.take_and_process_message().unwrap(); // m -> s1: InitializeSession
+		l.take_and_process_message().unwrap(); // m -> s2: InitializeSession
+		l.take_and_process_message().unwrap(); // s1 -> m: ConfirmInitialization
+		l.take_and_process_message().unwrap(); // s2 -> m: ConfirmInitialization
+		l.take_and_process_message().unwrap(); // m -> s1: CompleteInitialization
+		l.take_and_process_message().unwrap(); // m -> s2: CompleteInitialization
+		let message = message::KeysDissemination::new(&sid, &l.master().nonce().unwrap(), l.second_slave().node(),
+			&math::generate_random_scalar().unwrap(), &math::generate_random_scalar().unwrap(),
+			&[math::generate_random_point().unwrap()]).unwrap();
+		assert_eq!(l.first_slave().on_keys_dissemination(m, &message).unwrap_err(), Error::InvalidMessage);
 	}
 
 	#[test]
@@ -1122,6 +1183,7 @@ mod tests {
 		let (sid, _, s, l) = make_simple_cluster(1, 3).unwrap();
 		assert_eq!(l.master().on_public_key_share(s, &message::PublicKeyShare {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			public_share: math::generate_random_point().unwrap().into(),
 		}).unwrap_err(), Error::InvalidStateForRequest);
 	}
@@ -1150,6 +1212,7 @@ mod tests {
 		l.process_message((f, t, Message::Encryption(EncryptionMessage::PublicKeyShare(msg.clone())))).unwrap();
 		assert_eq!(l.second_slave().on_public_key_share(m, &message::PublicKeyShare {
 			session: sid.into(),
+			session_nonce: l.master().nonce().unwrap().into(),
 			public_share: math::generate_random_point().unwrap().into(),
 		}).unwrap_err(), Error::InvalidMessage);
 	}
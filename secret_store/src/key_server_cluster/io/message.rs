@@ -25,7 +25,8 @@ use ethkey::{Public, Secret, KeyPair};
 use ethkey::math::curve_order;
 use util::{H256, U256};
 use key_server_cluster::Error;
-use key_server_cluster::message::{Message, ClusterMessage, EncryptionMessage, DecryptionMessage};
+use key_server_cluster::message::{Message, ClusterMessage, EncryptionMessage, DecryptionMessage, ShareAddMessage,
+	ServersSetChangeMessage, CURRENT_VERSION, is_compatible};
 
 /// Size of serialized header.
 pub const MESSAGE_HEADER_SIZE: usize = 4;
@@ -61,38 +62,57 @@ impl Into<Vec<u8>> for SerializedMessage {
 
 /// Serialize message.
 pub fn serialize_message(message: Message) -> Result<SerializedMessage, Error> {
-	let (message_kind, payload) = match message {
-		Message::Cluster(ClusterMessage::NodePublicKey(payload))							=> (1, serde_json::to_vec(&payload)),
-		Message::Cluster(ClusterMessage::NodePrivateKeySignature(payload))					=> (2, serde_json::to_vec(&payload)),
-		Message::Cluster(ClusterMessage::KeepAlive(payload))								=> (3, serde_json::to_vec(&payload)),
-		Message::Cluster(ClusterMessage::KeepAliveResponse(payload))						=> (4, serde_json::to_vec(&payload)),
-
-		Message::Encryption(EncryptionMessage::InitializeSession(payload))					=> (50, serde_json::to_vec(&payload)),
-		Message::Encryption(EncryptionMessage::ConfirmInitialization(payload))				=> (51, serde_json::to_vec(&payload)),
-		Message::Encryption(EncryptionMessage::CompleteInitialization(payload))				=> (52, serde_json::to_vec(&payload)),
-		Message::Encryption(EncryptionMessage::KeysDissemination(payload))					=> (53, serde_json::to_vec(&payload)),
-		Message::Encryption(EncryptionMessage::PublicKeyShare(payload))						=> (54, serde_json::to_vec(&payload)),
-		Message::Encryption(EncryptionMessage::SessionError(payload))						=> (55, serde_json::to_vec(&payload)),
-		Message::Encryption(EncryptionMessage::SessionCompleted(payload))					=> (56, serde_json::to_vec(&payload)),
-
-		Message::Decryption(DecryptionMessage::InitializeDecryptionSession(payload))		=> (100, serde_json::to_vec(&payload)),
-		Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(payload))	=> (101, serde_json::to_vec(&payload)),
-		Message::Decryption(DecryptionMessage::RequestPartialDecryption(payload))			=> (102, serde_json::to_vec(&payload)),
-		Message::Decryption(DecryptionMessage::PartialDecryption(payload))					=> (103, serde_json::to_vec(&payload)),
-		Message::Decryption(DecryptionMessage::DecryptionSessionError(payload))				=> (104, serde_json::to_vec(&payload)),
-		Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(payload))			=> (105, serde_json::to_vec(&payload)),
+	let message_kind = message.type_id();
+	let payload = match message {
+		Message::Cluster(ClusterMessage::NodePublicKey(payload))							=> serde_json::to_vec(&payload),
+		Message::Cluster(ClusterMessage::NodePrivateKeySignature(payload))					=> serde_json::to_vec(&payload),
+		Message::Cluster(ClusterMessage::KeepAlive(payload))								=> serde_json::to_vec(&payload),
+		Message::Cluster(ClusterMessage::KeepAliveResponse(payload))						=> serde_json::to_vec(&payload),
+
+		Message::Encryption(EncryptionMessage::InitializeSession(payload))					=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::ConfirmInitialization(payload))				=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::CompleteInitialization(payload))				=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::KeysDissemination(payload))					=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::PublicKeyShare(payload))						=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::SessionError(payload))						=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::SessionCompleted(payload))					=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::SessionKeepAlive(payload))					=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::Complaint(payload))							=> serde_json::to_vec(&payload),
+		Message::Encryption(EncryptionMessage::ComplaintResponse(payload))					=> serde_json::to_vec(&payload),
+
+		Message::Decryption(DecryptionMessage::InitializeDecryptionSession(payload))		=> serde_json::to_vec(&payload),
+		Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(payload))	=> serde_json::to_vec(&payload),
+		Message::Decryption(DecryptionMessage::RequestPartialDecryption(payload))			=> serde_json::to_vec(&payload),
+		Message::Decryption(DecryptionMessage::PartialDecryption(payload))					=> serde_json::to_vec(&payload),
+		Message::Decryption(DecryptionMessage::DecryptionSessionError(payload))				=> serde_json::to_vec(&payload),
+		Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(payload))			=> serde_json::to_vec(&payload),
+
+		Message::ShareAdd(ShareAddMessage::ShareAddConsensusInitiate(payload))				=> serde_json::to_vec(&payload),
+		Message::ShareAdd(ShareAddMessage::NewKeysDissemination(payload))					=> serde_json::to_vec(&payload),
+		Message::ShareAdd(ShareAddMessage::ShareAddConfirm(payload))						=> serde_json::to_vec(&payload),
+
+		Message::ServersSetChange(ServersSetChangeMessage::ConsensusInitiate(payload))		=> serde_json::to_vec(&payload),
+		Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(payload))			=> serde_json::to_vec(&payload),
+		Message::ServersSetChange(ServersSetChangeMessage::ChangeComplete(payload))			=> serde_json::to_vec(&payload),
+
+		Message::Batch(payload)																=> serde_json::to_vec(&payload),
+		Message::Retransmit(payload)														=> serde_json::to_vec(&payload),
 	};
 
 	let payload = payload.map_err(|err| Error::Serde(err.to_string()))?;
 	build_serialized_message(MessageHeader {
 		kind: message_kind,
-		version: 1,
+		version: CURRENT_VERSION,
 		size: 0,
 	}, payload)
 }
 
 /// Deserialize message.
 pub fn deserialize_message(header: &MessageHeader, payload: Vec<u8>) -> Result<Message, Error> {
+	if !is_compatible(header.version) {
+		return Err(Error::Serde(format!("incompatible message version {}", header.version)));
+	}
+
 	Ok(match header.kind {
 		1	=> Message::Cluster(ClusterMessage::NodePublicKey(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 		2	=> Message::Cluster(ClusterMessage::NodePrivateKeySignature(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
@@ -106,6 +126,9 @@ pub fn deserialize_message(header: &MessageHeader, payload: Vec<u8>) -> Result<M
 		54	=> Message::Encryption(EncryptionMessage::PublicKeyShare(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 		55	=> Message::Encryption(EncryptionMessage::SessionError(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 		56	=> Message::Encryption(EncryptionMessage::SessionCompleted(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		57	=> Message::Encryption(EncryptionMessage::SessionKeepAlive(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		58	=> Message::Encryption(EncryptionMessage::Complaint(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		59	=> Message::Encryption(EncryptionMessage::ComplaintResponse(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 
 		100	=> Message::Decryption(DecryptionMessage::InitializeDecryptionSession(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 		101	=> Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
@@ -114,6 +137,17 @@ pub fn deserialize_message(header: &MessageHeader, payload: Vec<u8>) -> Result<M
 		104	=> Message::Decryption(DecryptionMessage::DecryptionSessionError(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 		105	=> Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
 
+		150	=> Message::ShareAdd(ShareAddMessage::ShareAddConsensusInitiate(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		151	=> Message::ShareAdd(ShareAddMessage::NewKeysDissemination(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		152	=> Message::ShareAdd(ShareAddMessage::ShareAddConfirm(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+
+		200	=> Message::ServersSetChange(ServersSetChangeMessage::ConsensusInitiate(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		201	=> Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+		202	=> Message::ServersSetChange(ServersSetChangeMessage::ChangeComplete(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?)),
+
+		250	=> Message::Batch(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?),
+		251	=> Message::Retransmit(serde_json::from_slice(&payload).map_err(|err| Error::Serde(err.to_string()))?),
+
 		_ => return Err(Error::Serde(format!("unknown message type {}", header.kind))),
 	})
 }
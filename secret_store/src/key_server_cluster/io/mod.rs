@@ -25,8 +25,8 @@ mod write_message;
 
 pub use self::deadline::{deadline, Deadline, DeadlineStatus};
 pub use self::handshake::{handshake, accept_handshake, Handshake, HandshakeResult};
-pub use self::message::{MessageHeader, SerializedMessage, serialize_message, deserialize_message,
-	encrypt_message, compute_shared_key};
+pub use self::message::{MESSAGE_HEADER_SIZE, MessageHeader, SerializedMessage, serialize_message, deserialize_message,
+	deserialize_header, encrypt_message, compute_shared_key};
 pub use self::read_header::{read_header, ReadHeader};
 pub use self::read_payload::{read_payload, read_encrypted_payload, ReadPayload};
 pub use self::read_message::{read_message, read_encrypted_message, ReadMessage};
@@ -15,15 +15,68 @@
 // along with Parity.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::fmt;
+use std::cmp::{self, Ordering};
 use std::collections::{BTreeSet, BTreeMap};
-use ethkey::Secret;
-use key_server_cluster::SessionId;
-use super::{SerializableH256, SerializablePublic, SerializableSecret, SerializableSignature};
+use serde_json;
+use ethkey::{self, Public, Secret, Signature};
+use ethcrypto::ecies::{encrypt_single_message, decrypt_single_message};
+use util::{H256, Hashable};
+use key_server_cluster::{Error, NodeId, SessionId};
+use key_server_cluster::math;
+use key_server_cluster::io::{MESSAGE_HEADER_SIZE, serialize_message, deserialize_message, deserialize_header};
+use super::{SerializableH256, SerializablePublic, SerializableSecret, SerializableSignature, SerializableBytes,
+	SerializableCompressedPublic};
 
 pub type MessageSessionId = SerializableH256;
 pub type MessageNodeId = SerializablePublic;
 
-#[derive(Clone, Debug)]
+/// Version of the cluster protocol, implemented by this node.
+pub const CURRENT_VERSION: u8 = 2;
+
+/// Maximal number of messages in single batch.
+pub const MAX_BATCH_SIZE: usize = 64;
+
+/// Returns true if messages of given protocol version could be processed by this node.
+pub fn is_compatible(version: u8) -> bool {
+	version == CURRENT_VERSION
+}
+
+/// Kind of the session, which message belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SessionKind {
+	/// Encryption (DKG) session.
+	Encryption,
+	/// Decryption session.
+	Decryption,
+	/// Share add session.
+	ShareAdd,
+	/// Servers set change session.
+	ServersSetChange,
+}
+
+/// Id of the session along with its kind. Sessions of different kinds may share the same id,
+/// so the id alone is not enough to identify the session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopedSessionId {
+	/// Session kind.
+	pub kind: SessionKind,
+	/// Session id.
+	pub id: SessionId,
+	/// Decryption session id. Several decryption sessions of the same key may run concurrently.
+	pub sub_session: Option<Secret>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Message, tagged with the version of the protocol it has been created for.
+pub struct VersionedMessage {
+	/// Protocol version.
+	pub version: u8,
+	/// Message itself.
+	pub message: Message,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
 /// All possible messages that can be sent during encryption/decryption sessions.
 pub enum Message {
 	/// Cluster message.
@@ -32,9 +85,60 @@ pub enum Message {
 	Encryption(EncryptionMessage),
 	/// Decryption message.
 	Decryption(DecryptionMessage),
+	/// Share add message.
+	ShareAdd(ShareAddMessage),
+	/// Servers set change message.
+	ServersSetChange(ServersSetChangeMessage),
+	/// Several messages of the same session, sent in single frame.
+	Batch(MessageBatch),
+	/// Request to resend the message, which has been lost in transport.
+	Retransmit(Retransmit),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Several messages of the same session, which are processed by the receiver in order.
+pub struct MessageBatch {
+	/// Batched messages.
+	pub messages: Vec<Message>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Request to resend the session message of given type, which has been sent by `from` node to the requestor,
+/// but has never been received. The session is not restarted: the message is resent only if the
+/// `from` node still holds it.
+pub struct Retransmit {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Type id of the missing message.
+	pub missing_type: u8,
+	/// Node, which has sent the missing message.
+	pub from: MessageNodeId,
+	/// Decryption session Id. Must be set if the missing message belongs to decryption session.
+	pub sub_session: Option<SerializableSecret>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Message, signed with the sender node key.
+pub struct SignedMessage {
+	/// Message itself.
+	pub message: Message,
+	/// Signature of the serialized message hash.
+	pub signature: SerializableSignature,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Message along with its sender and recipient.
+pub struct Envelope {
+	/// Sender node.
+	pub from: MessageNodeId,
+	/// Recipient node. When None, message is broadcasted to all nodes.
+	pub to: Option<MessageNodeId>,
+	/// Message itself.
+	pub message: Message,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 /// All possible cluster-level messages.
 pub enum ClusterMessage {
 	/// Introduce node public key.
@@ -47,7 +151,8 @@ pub enum ClusterMessage {
 	KeepAliveResponse(KeepAliveResponse),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 /// All possible messages that can be sent during encryption session.
 pub enum EncryptionMessage {
 	/// Initialize new DKG session.
@@ -64,10 +169,26 @@ pub enum EncryptionMessage {
 	SessionError(SessionError),
 	/// When session is completed.
 	SessionCompleted(SessionCompleted),
+	/// Session participant is still alive.
+	SessionKeepAlive(SessionKeepAlive),
+	/// Node complains about invalid keys, received during KD phase.
+	Complaint(Complaint),
+	/// Accused node reveals secrets it has sent to the complainant.
+	ComplaintResponse(ComplaintResponse),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 /// All possible messages that can be sent during decryption session.
+///
+/// Decryption session is coordinated by the node, which has received decryption request:
+/// 1) coordinator sends `InitializeDecryptionSession` to every other node, holding the key share;
+/// 2) every node responds with `ConfirmDecryptionInitialization`, confirming or rejecting the request;
+/// 3) once `threshold + 1` nodes (including coordinator) have confirmed, coordinator sends
+///    `RequestPartialDecryption` with the set of confirmed nodes to every confirmed node;
+/// 4) every requested node responds with `PartialDecryption`, holding its shadow point;
+/// 5) when coordinator has collected `threshold + 1` shadow points, it joins them to get decrypted secret
+///    and broadcasts `DecryptionSessionCompleted`.
 pub enum DecryptionMessage {
 	/// Initialize decryption session.
 	InitializeDecryptionSession(InitializeDecryptionSession),
@@ -83,6 +204,43 @@ pub enum DecryptionMessage {
 	DecryptionSessionCompleted(DecryptionSessionCompleted),
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+/// All possible messages that can be sent during share add session.
+///
+/// Share add session gives key shares to new nodes, without regenerating the key:
+/// 1) master node sends `ShareAddConsensusInitiate` with the set of new nodes to every old and new node;
+/// 2) every old node, holding the key share, sends `NewKeysDissemination` with its subshare to every new node;
+/// 3) every new node computes its key share from `threshold + 1` subshares and responds with `ShareAddConfirm`.
+/// Key threshold is preserved: after the session, any `threshold + 1` nodes of the extended set are able to
+/// decrypt secret, encrypted with the same joint public.
+pub enum ShareAddMessage {
+	/// Initialize share add session.
+	ShareAddConsensusInitiate(ShareAddConsensusInitiate),
+	/// Subshare of the key share is sent to the new node.
+	NewKeysDissemination(NewKeysDissemination),
+	/// New node has computed its key share.
+	ShareAddConfirm(ShareAddConfirm),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+/// All possible messages that can be sent during servers set change session.
+///
+/// Servers set change session moves the key from the old set of servers to the new one:
+/// 1) master node sends `ConsensusInitiate` with both old and new sets to every node of both sets;
+/// 2) every node of the old set responds with `ConsensusVote`;
+/// 3) the change proceeds only if a quorum (more than a half) of the old set has approved it,
+///    and master node broadcasts `ChangeComplete` once the key has been moved.
+pub enum ServersSetChangeMessage {
+	/// Initialize servers set change consensus.
+	ConsensusInitiate(ConsensusInitiate),
+	/// Node of the old set votes for/against the change.
+	ConsensusVote(ConsensusVote),
+	/// Servers set change is completed.
+	ChangeComplete(ChangeComplete),
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// Introduce node public key.
 pub struct NodePublicKey {
@@ -111,10 +269,13 @@ pub struct KeepAliveResponse {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-/// Initialize new DKG session.
+/// Initialize new DKG session. Every other message of this session must carry the same nonce
+/// as this message, so that messages of other session (with the same id) could not be replayed.
 pub struct InitializeSession {
 	/// Session Id.
 	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
 	/// Derived generation point. Starting from originator, every node must multiply this
 	/// point by random scalar (unknown by other nodes). At the end of initialization
 	/// `point` will be some (k1 * k2 * ... * kn) * G = `point` where `(k1 * k2 * ... * kn)`
@@ -127,6 +288,8 @@ pub struct InitializeSession {
 pub struct ConfirmInitialization {
 	/// Session Id.
 	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
 	/// Derived generation point.
 	pub derived_point: SerializablePublic,
 }
@@ -136,6 +299,8 @@ pub struct ConfirmInitialization {
 pub struct CompleteInitialization {
 	/// Session Id.
 	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
 	/// All session participants along with their identification numbers.
 	pub nodes: BTreeMap<MessageNodeId, SerializableSecret>,
 	/// Decryption threshold. During decryption threshold-of-route.len() nodes must came to
@@ -146,16 +311,45 @@ pub struct CompleteInitialization {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-/// Generated keys are sent to every node.
+/// Generated keys are sent to every node. Secret values are encrypted with the recipient node public key.
 pub struct KeysDissemination {
 	/// Session Id.
 	pub session: MessageSessionId,
-	/// Secret 1.
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
+	/// Secret 1, encrypted with the recipient public key.
+	pub secret1: SerializableBytes,
+	/// Secret 2, encrypted with the recipient public key.
+	pub secret2: SerializableBytes,
+	/// Public values, serialized in compressed form.
+	pub publics: Vec<SerializableCompressedPublic>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Node complains that it has received invalid keys from other node during KD phase.
+/// Blame phase is not yet run by encryption session, so the message is rejected on receive.
+pub struct Complaint {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
+	/// Node, which has sent invalid keys.
+	pub against: MessageNodeId,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Node, against which complaint has been made, reveals secret values it has sent to the complainant.
+pub struct ComplaintResponse {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
+	/// Complainant node.
+	pub responding_to: MessageNodeId,
+	/// Secret 1, sent to the complainant.
 	pub secret1: SerializableSecret,
-	/// Secret 2.
+	/// Secret 2, sent to the complainant.
 	pub secret2: SerializableSecret,
-	/// Public values.
-	pub publics: Vec<SerializablePublic>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -163,16 +357,22 @@ pub struct KeysDissemination {
 pub struct PublicKeyShare {
 	/// Session Id.
 	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
 	/// Public key share.
 	pub public_share: SerializablePublic,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-/// When session error has occured.
+/// When session error has occured. Broadcasted by the node, which has detected the failure, so that
+/// other participants could free session resources without waiting for timeout.
+/// Receiving node must move its local session into the terminal `Failed` state.
 pub struct SessionError {
 	/// Session Id.
 	pub session: MessageSessionId,
-	/// Public key share.
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
+	/// Error description.
 	pub error: String,
 }
 
@@ -181,12 +381,28 @@ pub struct SessionError {
 pub struct SessionCompleted {
 	/// Session Id.
 	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
 	/// Common (shared) encryption point.
 	pub common_point: SerializablePublic,
 	/// Encrypted point.
 	pub encrypted_point: SerializablePublic,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Session participant is still alive. Broadcasted by every participant every 10 seconds while the session
+/// is active. Receiving node does not change session state, but updates the time of the last message received
+/// from the sender in this session. Session fails if some participant has not sent keep alive for 30 seconds.
+pub struct SessionKeepAlive {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Session nonce, generated by master node on session initialization.
+	pub session_nonce: SerializableH256,
+	/// Sequence number of the keep alive message within session. Messages with sequence number,
+	/// which is not greater than the number of the last message from the same node, are ignored.
+	pub seq: u64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// Node is requested to decrypt data, encrypted in given session.
 pub struct InitializeDecryptionSession {
@@ -236,6 +452,60 @@ pub struct PartialDecryption {
 	pub decrypt_shadow: Option<Vec<u8>>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Initialize share add session.
+pub struct ShareAddConsensusInitiate {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Nodes, which are joining the cluster.
+	pub new_nodes: BTreeSet<MessageNodeId>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Subshare of the key share is sent to the new node. Secret values are encrypted with the recipient node public key.
+pub struct NewKeysDissemination {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Subshare of the sender key share, encrypted with the recipient public key.
+	pub secret_subshare: SerializableBytes,
+	/// Public values.
+	pub publics: Vec<SerializableCompressedPublic>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// New node has computed its key share.
+pub struct ShareAddConfirm {
+	/// Session Id.
+	pub session: MessageSessionId,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Initialize servers set change consensus.
+pub struct ConsensusInitiate {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Nodes, currently holding the key.
+	pub old_set: BTreeSet<MessageNodeId>,
+	/// Nodes, which will hold the key after the change.
+	pub new_set: BTreeSet<MessageNodeId>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Node of the old set votes for/against the change.
+pub struct ConsensusVote {
+	/// Session Id.
+	pub session: MessageSessionId,
+	/// Is change approved by the node?
+	pub approve: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Servers set change is completed.
+pub struct ChangeComplete {
+	/// Session Id.
+	pub session: MessageSessionId,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// When decryption session error has occured.
 pub struct DecryptionSessionError {
@@ -256,6 +526,767 @@ pub struct DecryptionSessionCompleted {
 	pub sub_session: SerializableSecret,
 }
 
+impl Message {
+	/// Id of the session this message belongs to. Cluster messages are not bound to any session.
+	pub fn session_id(&self) -> Option<&SessionId> {
+		match *self {
+			Message::Cluster(_) => None,
+			Message::Encryption(ref message) => Some(message.session_id()),
+			Message::Decryption(ref message) => Some(message.session_id()),
+			Message::ShareAdd(ref message) => Some(message.session_id()),
+			Message::ServersSetChange(ref message) => Some(message.session_id()),
+			Message::Batch(ref batch) => batch.messages.first().and_then(|message| message.session_id()),
+			Message::Retransmit(ref message) => Some(&message.session),
+		}
+	}
+
+	/// Id of the session this message belongs to, scoped with the session kind.
+	/// The kind of the session, which `Retransmit` is sent in, is implied by the type of the missing message.
+	pub fn scoped_session_id(&self) -> Option<ScopedSessionId> {
+		let kind = match *self {
+			Message::Batch(ref batch) => return batch.messages.first().and_then(|message| message.scoped_session_id()),
+			Message::Retransmit(ref message) => return message.scoped_session_id(),
+			Message::Decryption(ref message) => return Some(ScopedSessionId::decryption(message.session_id().clone(), message.sub_session_id().clone())),
+			_ => SessionKind::from_type_id(self.type_id()),
+		};
+
+		match (kind, self.session_id()) {
+			(Some(kind), Some(id)) => Some(ScopedSessionId::new(kind, id.clone())),
+			_ => None,
+		}
+	}
+
+	/// Numeric message type id, which is sent in the message header before the payload.
+	/// Ids are append-only: id of existing message must never change and ids of removed messages must never be reused.
+	pub fn type_id(&self) -> u8 {
+		match *self {
+			Message::Cluster(ClusterMessage::NodePublicKey(_)) => 1,
+			Message::Cluster(ClusterMessage::NodePrivateKeySignature(_)) => 2,
+			Message::Cluster(ClusterMessage::KeepAlive(_)) => 3,
+			Message::Cluster(ClusterMessage::KeepAliveResponse(_)) => 4,
+
+			Message::Encryption(EncryptionMessage::InitializeSession(_)) => 50,
+			Message::Encryption(EncryptionMessage::ConfirmInitialization(_)) => 51,
+			Message::Encryption(EncryptionMessage::CompleteInitialization(_)) => 52,
+			Message::Encryption(EncryptionMessage::KeysDissemination(_)) => 53,
+			Message::Encryption(EncryptionMessage::PublicKeyShare(_)) => 54,
+			Message::Encryption(EncryptionMessage::SessionError(_)) => 55,
+			Message::Encryption(EncryptionMessage::SessionCompleted(_)) => 56,
+			Message::Encryption(EncryptionMessage::SessionKeepAlive(_)) => 57,
+			Message::Encryption(EncryptionMessage::Complaint(_)) => 58,
+			Message::Encryption(EncryptionMessage::ComplaintResponse(_)) => 59,
+
+			Message::Decryption(DecryptionMessage::InitializeDecryptionSession(_)) => 100,
+			Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(_)) => 101,
+			Message::Decryption(DecryptionMessage::RequestPartialDecryption(_)) => 102,
+			Message::Decryption(DecryptionMessage::PartialDecryption(_)) => 103,
+			Message::Decryption(DecryptionMessage::DecryptionSessionError(_)) => 104,
+			Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(_)) => 105,
+
+			Message::ShareAdd(ShareAddMessage::ShareAddConsensusInitiate(_)) => 150,
+			Message::ShareAdd(ShareAddMessage::NewKeysDissemination(_)) => 151,
+			Message::ShareAdd(ShareAddMessage::ShareAddConfirm(_)) => 152,
+
+			Message::ServersSetChange(ServersSetChangeMessage::ConsensusInitiate(_)) => 200,
+			Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(_)) => 201,
+			Message::ServersSetChange(ServersSetChangeMessage::ChangeComplete(_)) => 202,
+
+			Message::Batch(_) => 250,
+			Message::Retransmit(_) => 251,
+		}
+	}
+
+	/// Encode message into a self-delimiting frame: the message header (version, type id
+	/// and payload length), followed by the serialized payload.
+	pub fn encode(&self) -> Result<Vec<u8>, Error> {
+		serialize_message(self.clone()).map(Into::into)
+	}
+
+	/// Decode message from a frame, produced by `encode`.
+	/// Fails if the frame is truncated, has trailing data or an unknown type id.
+	pub fn decode(data: &[u8]) -> Result<Message, Error> {
+		if data.len() < MESSAGE_HEADER_SIZE {
+			return Err(Error::InvalidMessage);
+		}
+
+		let header = deserialize_header(&data[..MESSAGE_HEADER_SIZE])?;
+		if data.len() - MESSAGE_HEADER_SIZE != header.size as usize {
+			return Err(Error::InvalidMessage);
+		}
+
+		deserialize_message(&header, data[MESSAGE_HEADER_SIZE..].to_vec())
+	}
+
+	/// Check that message collections are not larger than the cluster could ever require.
+	/// Should be called before any processing of the message, including cryptographic.
+	pub fn validate_size(&self, max_nodes: usize) -> Result<(), Error> {
+		let size = match *self {
+			Message::Encryption(EncryptionMessage::CompleteInitialization(ref msg)) => msg.nodes.len(),
+			Message::Encryption(EncryptionMessage::KeysDissemination(ref msg)) => msg.publics.len(),
+			Message::Decryption(DecryptionMessage::RequestPartialDecryption(ref msg)) => msg.nodes.len(),
+			Message::ShareAdd(ShareAddMessage::ShareAddConsensusInitiate(ref msg)) => msg.new_nodes.len(),
+			Message::ShareAdd(ShareAddMessage::NewKeysDissemination(ref msg)) => msg.publics.len(),
+			Message::ServersSetChange(ServersSetChangeMessage::ConsensusInitiate(ref msg)) =>
+				cmp::max(msg.old_set.len(), msg.new_set.len()),
+			Message::Batch(ref batch) => {
+				for message in &batch.messages {
+					message.validate_size(max_nodes)?;
+				}
+				0
+			},
+			_ => 0,
+		};
+
+		if size > max_nodes {
+			Err(Error::InvalidMessage)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Create node public key message.
+	pub fn node_public_key(node_id: &NodeId, confirmation_plain: &H256) -> Self {
+		Message::Cluster(ClusterMessage::NodePublicKey(NodePublicKey {
+			node_id: node_id.clone().into(),
+			confirmation_plain: confirmation_plain.clone().into(),
+		}))
+	}
+
+	/// Create node private key signature message.
+	pub fn node_private_key_signature(confirmation_signed: &Signature) -> Self {
+		Message::Cluster(ClusterMessage::NodePrivateKeySignature(NodePrivateKeySignature {
+			confirmation_signed: confirmation_signed.clone().into(),
+		}))
+	}
+
+	/// Create keep alive message.
+	pub fn keep_alive() -> Self {
+		Message::Cluster(ClusterMessage::KeepAlive(KeepAlive {}))
+	}
+
+	/// Create keep alive response message.
+	pub fn keep_alive_response() -> Self {
+		Message::Cluster(ClusterMessage::KeepAliveResponse(KeepAliveResponse {}))
+	}
+
+	/// Create encryption session initialization message.
+	pub fn initialize_session(session: &SessionId, session_nonce: &H256, derived_point: &Public) -> Self {
+		Message::Encryption(EncryptionMessage::InitializeSession(InitializeSession {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			derived_point: derived_point.clone().into(),
+		}))
+	}
+
+	/// Create encryption session initialization confirmation message.
+	pub fn confirm_initialization(session: &SessionId, session_nonce: &H256, derived_point: &Public) -> Self {
+		Message::Encryption(EncryptionMessage::ConfirmInitialization(ConfirmInitialization {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			derived_point: derived_point.clone().into(),
+		}))
+	}
+
+	/// Create encryption session initialization completion message.
+	pub fn complete_initialization(session: &SessionId, session_nonce: &H256, nodes: BTreeMap<NodeId, Secret>, threshold: usize, derived_point: Public) -> Result<Self, Error> {
+		CompleteInitialization::new(session, session_nonce, nodes, threshold, derived_point)
+			.map(|message| Message::Encryption(EncryptionMessage::CompleteInitialization(message)))
+	}
+
+	/// Create keys dissemination message for given recipient.
+	pub fn keys_dissemination(session: &SessionId, session_nonce: &H256, recipient: &Public, secret1: &Secret, secret2: &Secret, publics: &[Public]) -> Result<Self, Error> {
+		KeysDissemination::new(session, session_nonce, recipient, secret1, secret2, publics)
+			.map(|message| Message::Encryption(EncryptionMessage::KeysDissemination(message)))
+	}
+
+	/// Create public key share message.
+	pub fn public_key_share(session: &SessionId, session_nonce: &H256, public_share: &Public) -> Self {
+		Message::Encryption(EncryptionMessage::PublicKeyShare(PublicKeyShare {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			public_share: public_share.clone().into(),
+		}))
+	}
+
+	/// Create encryption session error message.
+	pub fn session_error(session: &SessionId, session_nonce: &H256, error: &Error) -> Self {
+		Message::Encryption(EncryptionMessage::SessionError(SessionError::new(session, session_nonce, error)))
+	}
+
+	/// Create encryption session completion message.
+	pub fn session_completed(session: &SessionId, session_nonce: &H256, common_point: &Public, encrypted_point: &Public) -> Self {
+		Message::Encryption(EncryptionMessage::SessionCompleted(SessionCompleted {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			common_point: common_point.clone().into(),
+			encrypted_point: encrypted_point.clone().into(),
+		}))
+	}
+
+	/// Create encryption session keep alive message.
+	pub fn session_keep_alive(session: &SessionId, session_nonce: &H256, seq: u64) -> Self {
+		Message::Encryption(EncryptionMessage::SessionKeepAlive(SessionKeepAlive {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			seq: seq,
+		}))
+	}
+
+	/// Create encryption session complaint message.
+	pub fn complaint(session: &SessionId, session_nonce: &H256, against: &NodeId) -> Self {
+		Message::Encryption(EncryptionMessage::Complaint(Complaint {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			against: against.clone().into(),
+		}))
+	}
+
+	/// Create encryption session complaint response message.
+	pub fn complaint_response(session: &SessionId, session_nonce: &H256, responding_to: &NodeId, secret1: &Secret, secret2: &Secret) -> Self {
+		Message::Encryption(EncryptionMessage::ComplaintResponse(ComplaintResponse {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			responding_to: responding_to.clone().into(),
+			secret1: secret1.clone().into(),
+			secret2: secret2.clone().into(),
+		}))
+	}
+
+	/// Create decryption session initialization message.
+	pub fn initialize_decryption_session(session: &SessionId, sub_session: &Secret, requestor_signature: &Signature, is_shadow_decryption: bool) -> Self {
+		Message::Decryption(DecryptionMessage::InitializeDecryptionSession(InitializeDecryptionSession {
+			session: session.clone().into(),
+			sub_session: sub_session.clone().into(),
+			requestor_signature: requestor_signature.clone().into(),
+			is_shadow_decryption: is_shadow_decryption,
+		}))
+	}
+
+	/// Create decryption session initialization confirmation message.
+	pub fn confirm_decryption_initialization(session: &SessionId, sub_session: &Secret, is_confirmed: bool) -> Self {
+		Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(ConfirmDecryptionInitialization {
+			session: session.clone().into(),
+			sub_session: sub_session.clone().into(),
+			is_confirmed: is_confirmed,
+		}))
+	}
+
+	/// Create partial decryption request for given set of confirmed nodes.
+	pub fn request_partial_decryption(session: &SessionId, sub_session: &Secret, nodes: &BTreeSet<NodeId>) -> Self {
+		Message::Decryption(DecryptionMessage::RequestPartialDecryption(RequestPartialDecryption::new(session, sub_session, nodes)))
+	}
+
+	/// Create partial decryption response.
+	pub fn partial_decryption(session: &SessionId, sub_session: &Secret, shadow_point: Public, decrypt_shadow: Option<Vec<u8>>) -> Self {
+		Message::Decryption(DecryptionMessage::PartialDecryption(PartialDecryption::new(session, sub_session, shadow_point, decrypt_shadow)))
+	}
+
+	/// Create decryption session error message.
+	pub fn decryption_session_error(session: &SessionId, sub_session: &Secret, error: &Error) -> Self {
+		Message::Decryption(DecryptionMessage::DecryptionSessionError(DecryptionSessionError {
+			session: session.clone().into(),
+			sub_session: sub_session.clone().into(),
+			error: format!("{:?}", error),
+		}))
+	}
+
+	/// Create decryption session completion message.
+	pub fn decryption_session_completed(session: &SessionId, sub_session: &Secret) -> Self {
+		Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(DecryptionSessionCompleted {
+			session: session.clone().into(),
+			sub_session: sub_session.clone().into(),
+		}))
+	}
+
+	/// Create share add consensus initiation message.
+	pub fn share_add_consensus_initiate(session: &SessionId, new_nodes: &BTreeSet<NodeId>) -> Self {
+		Message::ShareAdd(ShareAddMessage::ShareAddConsensusInitiate(ShareAddConsensusInitiate {
+			session: session.clone().into(),
+			new_nodes: new_nodes.iter().cloned().map(Into::into).collect(),
+		}))
+	}
+
+	/// Create new keys dissemination message for given recipient.
+	pub fn new_keys_dissemination(session: &SessionId, recipient: &Public, secret_subshare: &Secret, publics: &[Public]) -> Result<Self, Error> {
+		NewKeysDissemination::new(session, recipient, secret_subshare, publics)
+			.map(|message| Message::ShareAdd(ShareAddMessage::NewKeysDissemination(message)))
+	}
+
+	/// Create share add confirmation message.
+	pub fn share_add_confirm(session: &SessionId) -> Self {
+		Message::ShareAdd(ShareAddMessage::ShareAddConfirm(ShareAddConfirm {
+			session: session.clone().into(),
+		}))
+	}
+
+	/// Create servers set change consensus initiation message.
+	pub fn consensus_initiate(session: &SessionId, old_set: &BTreeSet<NodeId>, new_set: &BTreeSet<NodeId>) -> Self {
+		Message::ServersSetChange(ServersSetChangeMessage::ConsensusInitiate(ConsensusInitiate {
+			session: session.clone().into(),
+			old_set: old_set.iter().cloned().map(Into::into).collect(),
+			new_set: new_set.iter().cloned().map(Into::into).collect(),
+		}))
+	}
+
+	/// Create servers set change consensus vote message.
+	pub fn consensus_vote(session: &SessionId, approve: bool) -> Self {
+		Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(ConsensusVote {
+			session: session.clone().into(),
+			approve: approve,
+		}))
+	}
+
+	/// Create servers set change completion message.
+	pub fn change_complete(session: &SessionId) -> Self {
+		Message::ServersSetChange(ServersSetChangeMessage::ChangeComplete(ChangeComplete {
+			session: session.clone().into(),
+		}))
+	}
+
+	/// Create batch of messages, belonging to the same session.
+	pub fn batch(messages: Vec<Message>) -> Result<Self, Error> {
+		MessageBatch::new(messages).map(Message::Batch)
+	}
+
+	/// Create request to resend the message of given type, sent by `from` node.
+	pub fn retransmit(session: &SessionId, sub_session: Option<&Secret>, missing_type: u8, from: &NodeId) -> Self {
+		Message::Retransmit(Retransmit {
+			session: session.clone().into(),
+			missing_type: missing_type,
+			from: from.clone().into(),
+			sub_session: sub_session.cloned().map(Into::into),
+		})
+	}
+}
+
+impl SessionKind {
+	/// Kind of the session, which message with given type id belongs to.
+	/// Cluster messages, batches and retransmission requests are not bound to any session kind.
+	pub fn from_type_id(type_id: u8) -> Option<Self> {
+		match type_id {
+			50...99 => Some(SessionKind::Encryption),
+			100...149 => Some(SessionKind::Decryption),
+			150...199 => Some(SessionKind::ShareAdd),
+			200...249 => Some(SessionKind::ServersSetChange),
+			_ => None,
+		}
+	}
+}
+
+impl ScopedSessionId {
+	/// Create id of the session of given kind.
+	pub fn new(kind: SessionKind, id: SessionId) -> Self {
+		ScopedSessionId {
+			kind: kind,
+			id: id,
+			sub_session: None,
+		}
+	}
+
+	/// Create id of the decryption session.
+	pub fn decryption(id: SessionId, sub_session: Secret) -> Self {
+		ScopedSessionId {
+			kind: SessionKind::Decryption,
+			id: id,
+			sub_session: Some(sub_session),
+		}
+	}
+}
+
+impl PartialOrd for ScopedSessionId {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for ScopedSessionId {
+	fn cmp(&self, other: &Self) -> Ordering {
+		match (self.kind, &self.id).cmp(&(other.kind, &other.id)) {
+			Ordering::Equal => self.sub_session.as_ref().map(|s| &**s).cmp(&other.sub_session.as_ref().map(|s| &**s)),
+			r @ _ => r,
+		}
+	}
+}
+
+impl Retransmit {
+	/// Id of the session, which the missing message belongs to. Decryption sessions are only
+	/// identified when the request carries the sub-session.
+	pub fn scoped_session_id(&self) -> Option<ScopedSessionId> {
+		SessionKind::from_type_id(self.missing_type).map(|kind| ScopedSessionId {
+			kind: kind,
+			id: self.session.0.clone(),
+			sub_session: self.sub_session.clone().map(Into::into),
+		})
+	}
+}
+
+impl MessageBatch {
+	/// Create batch of messages, checking that all messages belong to the same session.
+	pub fn new(messages: Vec<Message>) -> Result<Self, Error> {
+		let batch = MessageBatch {
+			messages: messages,
+		};
+		batch.validate()?;
+		Ok(batch)
+	}
+
+	/// Check that batch is not empty, is not larger than `MAX_BATCH_SIZE` and all messages belong to the same session
+	/// (of the same kind and, for decryption sessions, the same sub-session). Retransmission requests can't be batched.
+	/// Must be called on received batch before processing any of its messages.
+	pub fn validate(&self) -> Result<(), Error> {
+		if self.messages.is_empty() || self.messages.len() > MAX_BATCH_SIZE {
+			return Err(Error::InvalidMessage);
+		}
+
+		let session_id = self.messages[0].scoped_session_id();
+		for message in &self.messages {
+			match *message {
+				Message::Cluster(_) | Message::Batch(_) | Message::Retransmit(_) => return Err(Error::InvalidMessage),
+				_ if message.scoped_session_id() != session_id => return Err(Error::InvalidSessionId),
+				_ => (),
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl VersionedMessage {
+	/// Tag message with the current protocol version.
+	pub fn new(message: Message) -> Self {
+		VersionedMessage {
+			version: CURRENT_VERSION,
+			message: message,
+		}
+	}
+
+	/// Returns true if message could be processed by this node.
+	pub fn is_compatible(&self) -> bool {
+		is_compatible(self.version)
+	}
+}
+
+impl SignedMessage {
+	/// Sign message with the node secret key.
+	pub fn sign(message: Message, secret: &Secret) -> Result<Self, Error> {
+		let signature = ethkey::sign(secret, &signed_message_hash(&message)?)?;
+		Ok(SignedMessage {
+			message: message,
+			signature: signature.into(),
+		})
+	}
+
+	/// Recover public key of the node, which has signed the message.
+	pub fn recover_sender(&self) -> Result<Public, Error> {
+		Ok(ethkey::recover(&self.signature, &signed_message_hash(&self.message)?)?)
+	}
+}
+
+fn signed_message_hash(message: &Message) -> Result<H256, Error> {
+	serde_json::to_vec(message)
+		.map(|message| message.sha3())
+		.map_err(|err| Error::Serde(err.to_string()))
+}
+
+impl Envelope {
+	/// Create envelope for message, broadcasted to all nodes.
+	pub fn broadcast(from: NodeId, message: Message) -> Self {
+		Envelope {
+			from: from.into(),
+			to: None,
+			message: message,
+		}
+	}
+
+	/// Create envelope for message, sent to the single node.
+	pub fn direct(from: NodeId, to: NodeId, message: Message) -> Self {
+		Envelope {
+			from: from.into(),
+			to: Some(to.into()),
+			message: message,
+		}
+	}
+
+	/// Is message broadcasted to all nodes?
+	pub fn is_broadcast(&self) -> bool {
+		self.to.is_none()
+	}
+}
+
+impl CompleteInitialization {
+	/// Create complete initialization message, checking that `threshold` is valid for given set of nodes.
+	/// Fields are public for deserialization only, so this should be used to build the message.
+	pub fn new(session: &SessionId, session_nonce: &H256, nodes: BTreeMap<NodeId, Secret>, threshold: usize, derived_point: Public) -> Result<Self, Error> {
+		if nodes.is_empty() {
+			return Err(Error::InvalidNodesCount);
+		}
+		if threshold >= nodes.len() {
+			return Err(Error::InvalidThreshold);
+		}
+
+		let message = CompleteInitialization {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			nodes: nodes.into_iter().map(|(id, number)| (id.into(), number.into())).collect(),
+			threshold: threshold,
+			derived_point: derived_point.into(),
+		};
+		message.validate_ids()?;
+		Ok(message)
+	}
+
+	/// Check that identification numbers of nodes are distinct and non-zero, as required by the DKG math.
+	pub fn validate_ids(&self) -> Result<(), Error> {
+		let ids: Vec<&Secret> = self.nodes.values().map(|id| &id.0).collect();
+		for (index, id) in ids.iter().enumerate() {
+			if id.is_zero() || ids[index + 1..].contains(id) {
+				return Err(Error::InvalidMessage);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl KeysDissemination {
+	/// Create keys dissemination message, encrypting secret values with the recipient public key.
+	pub fn new(session: &SessionId, session_nonce: &H256, recipient: &Public, secret1: &Secret, secret2: &Secret, publics: &[Public]) -> Result<Self, Error> {
+		Ok(KeysDissemination {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			secret1: encrypt_single_message(recipient, &**secret1)?.into(),
+			secret2: encrypt_single_message(recipient, &**secret2)?.into(),
+			publics: publics.iter().cloned().map(Into::into).collect(),
+		})
+	}
+
+	/// Check that there is exactly one public value per coefficient of the degree-`threshold` polynomial.
+	pub fn validate_len(&self, threshold: usize) -> Result<(), Error> {
+		if self.publics.len() != threshold + 1 {
+			return Err(Error::InvalidMessage);
+		}
+
+		Ok(())
+	}
+
+	/// Decrypt secret values with the recipient secret key.
+	pub fn decrypt_secrets(&self, recipient: &Secret) -> Result<(Secret, Secret), Error> {
+		Ok((decrypt_secret(recipient, &self.secret1)?, decrypt_secret(recipient, &self.secret2)?))
+	}
+}
+
+fn decrypt_secret(recipient: &Secret, encrypted: &[u8]) -> Result<Secret, Error> {
+	let secret = decrypt_single_message(recipient, encrypted).map_err(|_| Error::InvalidMessage)?;
+	if secret.len() != 32 {
+		return Err(Error::InvalidMessage);
+	}
+
+	Ok(Secret::from_slice(&secret))
+}
+
+impl NewKeysDissemination {
+	/// Create new keys dissemination message, encrypting secret subshare with the recipient public key.
+	pub fn new(session: &SessionId, recipient: &Public, secret_subshare: &Secret, publics: &[Public]) -> Result<Self, Error> {
+		Ok(NewKeysDissemination {
+			session: session.clone().into(),
+			secret_subshare: encrypt_single_message(recipient, &**secret_subshare)?.into(),
+			publics: publics.iter().cloned().map(Into::into).collect(),
+		})
+	}
+
+	/// Decrypt secret subshare with the recipient secret key.
+	pub fn decrypt_secret_subshare(&self, recipient: &Secret) -> Result<Secret, Error> {
+		decrypt_secret(recipient, &self.secret_subshare)
+	}
+}
+
+impl ConsensusInitiate {
+	/// Returns true if quorum of the old set has approved the change. Votes of nodes outside of the old set are ignored.
+	pub fn is_approved(&self, votes: &BTreeMap<NodeId, bool>) -> bool {
+		let approvals = votes.iter()
+			.filter(|&(node, approve)| *approve && self.old_set.iter().any(|n| **n == *node))
+			.count();
+		approvals * 2 > self.old_set.len()
+	}
+}
+
+/// Verify secrets, revealed in response to complaint, against commitments the accused node has published in KD phase.
+/// The threshold is implied by the number of commitments. Returns true if the accused node is exonerated
+/// and false if the complaint is confirmed.
+pub fn verify_shares(response: &ComplaintResponse, publics: &[Public], recipient_id: &Secret, derived_point: &Public) -> Result<bool, Error> {
+	if publics.is_empty() {
+		return Err(Error::InvalidMessage);
+	}
+
+	math::keys_verification(publics.len() - 1, derived_point, recipient_id, &response.secret1, &response.secret2, publics)
+}
+
+impl RequestPartialDecryption {
+	/// Create partial decryption request for given set of confirmed nodes.
+	pub fn new(session: &SessionId, sub_session: &Secret, nodes: &BTreeSet<NodeId>) -> Self {
+		RequestPartialDecryption {
+			session: session.clone().into(),
+			sub_session: sub_session.clone().into(),
+			nodes: nodes.iter().cloned().map(Into::into).collect(),
+		}
+	}
+}
+
+impl PartialDecryption {
+	/// Create partial decryption response.
+	pub fn new(session: &SessionId, sub_session: &Secret, shadow_point: Public, decrypt_shadow: Option<Vec<u8>>) -> Self {
+		PartialDecryption {
+			session: session.clone().into(),
+			sub_session: sub_session.clone().into(),
+			shadow_point: shadow_point.into(),
+			decrypt_shadow: decrypt_shadow,
+		}
+	}
+}
+
+impl SessionError {
+	/// Create session error message.
+	pub fn new(session: &SessionId, session_nonce: &H256, error: &Error) -> Self {
+		SessionError {
+			session: session.clone().into(),
+			session_nonce: session_nonce.clone().into(),
+			error: format!("{:?}", error),
+		}
+	}
+}
+
+/// Tally of initialization confirmations, received by the session master.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InitializationTally {
+	/// Nodes which are expected to confirm initialization.
+	pub expected: BTreeSet<NodeId>,
+	/// Nodes which have already confirmed initialization.
+	pub confirmed: BTreeSet<NodeId>,
+}
+
+impl InitializationTally {
+	/// Create tally, waiting for confirmations from given nodes.
+	pub fn new(expected: BTreeSet<NodeId>) -> Self {
+		InitializationTally {
+			expected: expected,
+			confirmed: BTreeSet::new(),
+		}
+	}
+
+	/// Record confirmation from given node. Repeated confirmations are ignored.
+	pub fn record(&mut self, node: NodeId) -> Result<(), Error> {
+		if !self.expected.contains(&node) {
+			return Err(Error::InvalidNodeId);
+		}
+
+		self.confirmed.insert(node);
+		Ok(())
+	}
+
+	/// Returns true if every expected node has confirmed initialization.
+	pub fn is_complete(&self) -> bool {
+		self.confirmed.len() == self.expected.len()
+	}
+}
+
+/// Session parameters, agreed upon in `CompleteInitialization` and used to validate later messages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgreedParameters {
+	/// Session threshold.
+	pub threshold: usize,
+}
+
+impl AgreedParameters {
+	/// Remember parameters from session initialization completion message.
+	pub fn new(message: &CompleteInitialization) -> Self {
+		AgreedParameters::with_threshold(message.threshold)
+	}
+
+	/// Remember parameters, chosen by the session master.
+	pub fn with_threshold(threshold: usize) -> Self {
+		AgreedParameters {
+			threshold: threshold,
+		}
+	}
+
+	/// Number of public values expected in `KeysDissemination` message.
+	pub fn expects_publics_len(&self) -> usize {
+		self.threshold + 1
+	}
+
+	/// Check that keys dissemination message is consistent with agreed parameters.
+	pub fn validate_keys_dissemination(&self, message: &KeysDissemination) -> Result<(), Error> {
+		message.validate_len(self.threshold)
+	}
+}
+
+/// Session messages, sent by this node, which could be resent in response to `Retransmit`.
+#[derive(Clone, Debug, Default)]
+pub struct SentMessages {
+	/// Last message of every type, sent to every node in every session.
+	pub messages: BTreeMap<(ScopedSessionId, u8, NodeId), Message>,
+}
+
+impl SentMessages {
+	/// Create empty cache.
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	/// Remember message, sent to given node. Messages, which are not bound to any session, are not cached.
+	pub fn record(&mut self, to: &NodeId, message: &Message) {
+		if let Some(session_id) = message.scoped_session_id() {
+			self.messages.insert((session_id, message.type_id(), to.clone()), message.clone());
+		}
+	}
+
+	/// Find message, which has been requested by `requestor`. Returns None if the request is addressed to other node
+	/// or if the message has never been sent (or is already forgotten).
+	pub fn lookup(&self, self_node_id: &NodeId, requestor: &NodeId, request: &Retransmit) -> Option<&Message> {
+		if *request.from != *self_node_id {
+			return None;
+		}
+
+		request.scoped_session_id()
+			.and_then(|session_id| self.messages.get(&(session_id, request.missing_type, requestor.clone())))
+	}
+
+	/// Forget all messages of given session.
+	pub fn remove_session(&mut self, session_id: &ScopedSessionId) {
+		self.messages.retain(|&(ref id, _, _), _| id != session_id);
+	}
+}
+
+/// Complaints, collected against nodes during KD phase.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ComplaintLedger {
+	/// Distinct complainants for every accused node.
+	pub complaints: BTreeMap<NodeId, BTreeSet<NodeId>>,
+}
+
+impl ComplaintLedger {
+	/// Create empty ledger.
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	/// Record complaint against `accused` node. Returns false if this complainant has already complained against it.
+	pub fn record(&mut self, accused: NodeId, complainant: NodeId) -> bool {
+		self.complaints.entry(accused).or_insert_with(BTreeSet::new).insert(complainant)
+	}
+
+	/// Number of distinct nodes, complaining against given node.
+	pub fn complainants_count(&self, node: &NodeId) -> usize {
+		self.complaints.get(node).map(|complainants| complainants.len()).unwrap_or(0)
+	}
+
+	/// Returns true if at least `threshold` distinct nodes have complained against given node.
+	/// Zero threshold never ejects: node is never ejected without complaints.
+	pub fn should_eject(&self, node: &NodeId, threshold: usize) -> bool {
+		threshold != 0 && self.complainants_count(node) >= threshold
+	}
+}
+
 impl EncryptionMessage {
 	pub fn session_id(&self) -> &SessionId {
 		match *self {
@@ -266,6 +1297,29 @@ impl EncryptionMessage {
 			EncryptionMessage::PublicKeyShare(ref msg) => &msg.session,
 			EncryptionMessage::SessionError(ref msg) => &msg.session,
 			EncryptionMessage::SessionCompleted(ref msg) => &msg.session,
+			EncryptionMessage::SessionKeepAlive(ref msg) => &msg.session,
+			EncryptionMessage::Complaint(ref msg) => &msg.session,
+			EncryptionMessage::ComplaintResponse(ref msg) => &msg.session,
+		}
+	}
+}
+
+impl ShareAddMessage {
+	pub fn session_id(&self) -> &SessionId {
+		match *self {
+			ShareAddMessage::ShareAddConsensusInitiate(ref msg) => &msg.session,
+			ShareAddMessage::NewKeysDissemination(ref msg) => &msg.session,
+			ShareAddMessage::ShareAddConfirm(ref msg) => &msg.session,
+		}
+	}
+}
+
+impl ServersSetChangeMessage {
+	pub fn session_id(&self) -> &SessionId {
+		match *self {
+			ServersSetChangeMessage::ConsensusInitiate(ref msg) => &msg.session,
+			ServersSetChangeMessage::ConsensusVote(ref msg) => &msg.session,
+			ServersSetChangeMessage::ChangeComplete(ref msg) => &msg.session,
 		}
 	}
 }
@@ -300,6 +1354,10 @@ impl fmt::Display for Message {
 			Message::Cluster(ref message) => write!(f, "Cluster.{}", message),
 			Message::Encryption(ref message) => write!(f, "Encryption.{}", message),
 			Message::Decryption(ref message) => write!(f, "Decryption.{}", message),
+			Message::ShareAdd(ref message) => write!(f, "ShareAdd.{}", message),
+			Message::ServersSetChange(ref message) => write!(f, "ServersSetChange.{}", message),
+			Message::Batch(ref batch) => write!(f, "Batch(messages={})", batch.messages.len()),
+			Message::Retransmit(ref msg) => write!(f, "Retransmit(session={}, missing_type={}, from={})", *msg.session, msg.missing_type, *msg.from),
 		}
 	}
 }
@@ -307,7 +1365,7 @@ impl fmt::Display for Message {
 impl fmt::Display for ClusterMessage {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
-			ClusterMessage::NodePublicKey(_) => write!(f, "NodePublicKey"),
+			ClusterMessage::NodePublicKey(ref msg) => write!(f, "NodePublicKey(node={})", *msg.node_id),
 			ClusterMessage::NodePrivateKeySignature(_) => write!(f, "NodePrivateKeySignature"),
 			ClusterMessage::KeepAlive(_) => write!(f, "KeepAlive"),
 			ClusterMessage::KeepAliveResponse(_) => write!(f, "KeepAliveResponse"),
@@ -315,29 +1373,822 @@ impl fmt::Display for ClusterMessage {
 	}
 }
 
+/// Secret values are never rendered, `<redacted>` is printed instead.
 impl fmt::Display for EncryptionMessage {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
-			EncryptionMessage::InitializeSession(_) => write!(f, "InitializeSession"),
-			EncryptionMessage::ConfirmInitialization(_) => write!(f, "ConfirmInitialization"),
-			EncryptionMessage::CompleteInitialization(_) => write!(f, "CompleteInitialization"),
-			EncryptionMessage::KeysDissemination(_) => write!(f, "KeysDissemination"),
-			EncryptionMessage::PublicKeyShare(_) => write!(f, "PublicKeyShare"),
-			EncryptionMessage::SessionError(ref msg) => write!(f, "SessionError({})", msg.error),
-			EncryptionMessage::SessionCompleted(_) => write!(f, "SessionCompleted"),
+			EncryptionMessage::InitializeSession(ref msg) =>
+				write!(f, "InitializeSession(session={})", *msg.session),
+			EncryptionMessage::ConfirmInitialization(ref msg) =>
+				write!(f, "ConfirmInitialization(session={})", *msg.session),
+			EncryptionMessage::CompleteInitialization(ref msg) =>
+				write!(f, "CompleteInitialization(session={}, nodes={}, threshold={})", *msg.session, msg.nodes.len(), msg.threshold),
+			EncryptionMessage::KeysDissemination(ref msg) =>
+				write!(f, "KeysDissemination(session={}, secret1=<redacted>, secret2=<redacted>, publics={})", *msg.session, msg.publics.len()),
+			EncryptionMessage::PublicKeyShare(ref msg) =>
+				write!(f, "PublicKeyShare(session={})", *msg.session),
+			EncryptionMessage::SessionError(ref msg) =>
+				write!(f, "SessionError(session={}, error={})", *msg.session, msg.error),
+			EncryptionMessage::SessionCompleted(ref msg) =>
+				write!(f, "SessionCompleted(session={})", *msg.session),
+			EncryptionMessage::SessionKeepAlive(ref msg) =>
+				write!(f, "SessionKeepAlive(session={}, seq={})", *msg.session, msg.seq),
+			EncryptionMessage::Complaint(ref msg) =>
+				write!(f, "Complaint(session={})", *msg.session),
+			EncryptionMessage::ComplaintResponse(ref msg) =>
+				write!(f, "ComplaintResponse(session={}, secret1=<redacted>, secret2=<redacted>)", *msg.session),
 		}
 	}
 }
 
+/// Secret values (including decryption session id) are never rendered, `<redacted>` is printed instead.
 impl fmt::Display for DecryptionMessage {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match *self {
-			DecryptionMessage::InitializeDecryptionSession(_) => write!(f, "InitializeDecryptionSession"),
-			DecryptionMessage::ConfirmDecryptionInitialization(_) => write!(f, "ConfirmDecryptionInitialization"),
-			DecryptionMessage::RequestPartialDecryption(_) => write!(f, "RequestPartialDecryption"),
-			DecryptionMessage::PartialDecryption(_) => write!(f, "PartialDecryption"),
-			DecryptionMessage::DecryptionSessionError(_) => write!(f, "DecryptionSessionError"),
-			DecryptionMessage::DecryptionSessionCompleted(_) => write!(f, "DecryptionSessionCompleted"),
+			DecryptionMessage::InitializeDecryptionSession(ref msg) =>
+				write!(f, "InitializeDecryptionSession(session={}, sub_session=<redacted>, shadow={})", *msg.session, msg.is_shadow_decryption),
+			DecryptionMessage::ConfirmDecryptionInitialization(ref msg) =>
+				write!(f, "ConfirmDecryptionInitialization(session={}, sub_session=<redacted>, confirmed={})", *msg.session, msg.is_confirmed),
+			DecryptionMessage::RequestPartialDecryption(ref msg) =>
+				write!(f, "RequestPartialDecryption(session={}, sub_session=<redacted>, nodes={})", *msg.session, msg.nodes.len()),
+			DecryptionMessage::PartialDecryption(ref msg) =>
+				write!(f, "PartialDecryption(session={}, sub_session=<redacted>)", *msg.session),
+			DecryptionMessage::DecryptionSessionError(ref msg) =>
+				write!(f, "DecryptionSessionError(session={}, sub_session=<redacted>, error={})", *msg.session, msg.error),
+			DecryptionMessage::DecryptionSessionCompleted(ref msg) =>
+				write!(f, "DecryptionSessionCompleted(session={}, sub_session=<redacted>)", *msg.session),
+		}
+	}
+}
+
+impl fmt::Display for ShareAddMessage {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ShareAddMessage::ShareAddConsensusInitiate(ref msg) =>
+				write!(f, "ShareAddConsensusInitiate(session={}, new_nodes={})", *msg.session, msg.new_nodes.len()),
+			ShareAddMessage::NewKeysDissemination(ref msg) =>
+				write!(f, "NewKeysDissemination(session={}, secret_subshare=<redacted>, publics={})", *msg.session, msg.publics.len()),
+			ShareAddMessage::ShareAddConfirm(ref msg) =>
+				write!(f, "ShareAddConfirm(session={})", *msg.session),
+		}
+	}
+}
+
+impl fmt::Display for ServersSetChangeMessage {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ServersSetChangeMessage::ConsensusInitiate(ref msg) =>
+				write!(f, "ConsensusInitiate(session={}, old_set={}, new_set={})", *msg.session, msg.old_set.len(), msg.new_set.len()),
+			ServersSetChangeMessage::ConsensusVote(ref msg) =>
+				write!(f, "ConsensusVote(session={}, approve={})", *msg.session, msg.approve),
+			ServersSetChangeMessage::ChangeComplete(ref msg) =>
+				write!(f, "ChangeComplete(session={})", *msg.session),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::{BTreeSet, BTreeMap};
+	use serde_json;
+	use rustc_serialize::hex::ToHex;
+	use ethkey::{Random, Generator};
+	use super::*;
+
+	fn roundtrip(message: Message) {
+		let serialized = serde_json::to_string(&message).unwrap();
+		let deserialized: Message = serde_json::from_str(&serialized).unwrap();
+		assert_eq!(deserialized.to_string(), message.to_string());
+		assert_eq!(serde_json::to_string(&deserialized).unwrap(), serialized);
+	}
+
+	fn public() -> SerializablePublic {
+		Random.generate().unwrap().public().clone().into()
+	}
+
+	fn secret() -> SerializableSecret {
+		Random.generate().unwrap().secret().clone().into()
+	}
+
+	#[test]
+	fn display_does_not_render_secrets() {
+		let recipient = Random.generate().unwrap();
+		let secret1 = secret();
+		let secret2 = secret();
+		let publics = vec![public().0, public().0, public().0];
+		let message = Message::Encryption(EncryptionMessage::KeysDissemination(KeysDissemination::new(&Default::default(), &Default::default(),
+			recipient.public(), &secret1, &secret2, &publics).unwrap()));
+
+		let displayed = message.to_string();
+		assert!(displayed.starts_with("Encryption.KeysDissemination(session="));
+		assert!(displayed.contains("secret1=<redacted>, secret2=<redacted>, publics=3"));
+		assert!(!displayed.contains(&secret1.to_hex()));
+		assert!(!displayed.contains(&secret2.to_hex()));
+	}
+
+	#[test]
+	fn keys_dissemination_secrets_are_decrypted_by_recipient_only() {
+		let recipient = Random.generate().unwrap();
+		let secret1 = secret();
+		let secret2 = secret();
+		let message = KeysDissemination::new(&Default::default(), &Default::default(),
+			recipient.public(), &secret1, &secret2, &[public().0]).unwrap();
+
+		assert!(!message.secret1.to_hex().contains(&secret1.to_hex()));
+		assert_eq!(message.decrypt_secrets(recipient.secret()).unwrap(), (secret1.0, secret2.0));
+		assert!(message.decrypt_secrets(Random.generate().unwrap().secret()).is_err());
+	}
+
+	#[test]
+	fn keys_dissemination_with_too_many_publics_is_rejected() {
+		let recipient = Random.generate().unwrap();
+		let publics: Vec<_> = (0..4).map(|_| public().0).collect();
+		let message = Message::Encryption(EncryptionMessage::KeysDissemination(KeysDissemination::new(&Default::default(), &Default::default(),
+			recipient.public(), &secret(), &secret(), &publics).unwrap()));
+
+		assert_eq!(message.validate_size(4), Ok(()));
+		assert_eq!(message.validate_size(3), Err(Error::InvalidMessage));
+	}
+
+	#[test]
+	fn consensus_initiate_with_too_large_new_set_is_rejected() {
+		let old_set: BTreeSet<_> = (0..2).map(|_| public().0).collect();
+		let new_set: BTreeSet<_> = (0..5).map(|_| public().0).collect();
+		let message = Message::consensus_initiate(&Default::default(), &old_set, &new_set);
+
+		assert_eq!(message.validate_size(5), Ok(()));
+		assert_eq!(message.validate_size(4), Err(Error::InvalidMessage));
+	}
+
+	#[test]
+	fn keys_dissemination_is_validated_against_agreed_threshold() {
+		let nodes: BTreeMap<_, _> = (0..3).map(|_| (public().0, secret().0)).collect();
+		let initialization = CompleteInitialization::new(&Default::default(), &Default::default(), nodes, 1, public().0).unwrap();
+		let parameters = AgreedParameters::new(&initialization);
+		assert_eq!(parameters.expects_publics_len(), 2);
+
+		let recipient = Random.generate().unwrap();
+		let dissemination = |publics_len: usize| {
+			let publics: Vec<_> = (0..publics_len).map(|_| public().0).collect();
+			KeysDissemination::new(&Default::default(), &Default::default(), recipient.public(), &secret(), &secret(), &publics).unwrap()
+		};
+		assert_eq!(parameters.validate_keys_dissemination(&dissemination(2)), Ok(()));
+		assert_eq!(parameters.validate_keys_dissemination(&dissemination(3)), Err(Error::InvalidMessage));
+	}
+
+	#[test]
+	fn keys_dissemination_publics_count_must_match_threshold() {
+		let recipient = Random.generate().unwrap();
+		let publics: Vec<_> = (0..3).map(|_| public().0).collect();
+		let message = KeysDissemination::new(&Default::default(), &Default::default(), recipient.public(), &secret(), &secret(), &publics).unwrap();
+
+		assert_eq!(message.validate_len(2), Ok(()));
+		assert_eq!(message.validate_len(3), Err(Error::InvalidMessage));
+		assert_eq!(message.validate_len(1), Err(Error::InvalidMessage));
+	}
+
+	#[test]
+	fn partial_decryption_messages_display_does_not_render_sub_session() {
+		let sub_session = secret();
+		let nodes: BTreeSet<_> = (0..3).map(|_| public().0).collect();
+		let request = Message::Decryption(DecryptionMessage::RequestPartialDecryption(RequestPartialDecryption::new(&Default::default(), &sub_session, &nodes)));
+		let response = Message::Decryption(DecryptionMessage::PartialDecryption(PartialDecryption::new(&Default::default(), &sub_session, public().0, None)));
+
+		assert!(request.to_string().ends_with("sub_session=<redacted>, nodes=3)"));
+		assert!(response.to_string().ends_with("sub_session=<redacted>)"));
+		assert!(!request.to_string().contains(&sub_session.to_hex()));
+		assert!(!response.to_string().contains(&sub_session.to_hex()));
+	}
+
+	#[test]
+	fn complaint_response_is_verified_against_disseminated_publics() {
+		for threshold in 1..3 {
+			let derived_point = math::generate_random_point().unwrap();
+			let complainant_id_number = math::generate_random_scalar().unwrap();
+			let polynom1 = math::generate_random_polynom(threshold).unwrap();
+			let polynom2 = math::generate_random_polynom(threshold).unwrap();
+			let publics = math::public_values_generation(threshold, &derived_point, &polynom1, &polynom2).unwrap();
+
+			let mut response = ComplaintResponse {
+				session: SerializableH256(Default::default()),
+				session_nonce: SerializableH256(Default::default()),
+				responding_to: public(),
+				secret1: math::compute_polynom(&polynom1, &complainant_id_number).unwrap().into(),
+				secret2: math::compute_polynom(&polynom2, &complainant_id_number).unwrap().into(),
+			};
+			assert_eq!(verify_shares(&response, &publics, &complainant_id_number, &derived_point), Ok(true));
+			assert_eq!(verify_shares(&response, &[], &complainant_id_number, &derived_point), Err(Error::InvalidMessage));
+
+			response.secret1 = secret();
+			assert_eq!(verify_shares(&response, &publics, &complainant_id_number, &derived_point), Ok(false));
 		}
 	}
+
+	#[test]
+	fn complete_initialization_requires_valid_threshold() {
+		let nodes: BTreeMap<_, _> = (0..3).map(|_| (public().0, secret().0)).collect();
+		assert!(CompleteInitialization::new(&Default::default(), &Default::default(), nodes.clone(), 2, public().0).is_ok());
+		assert_eq!(CompleteInitialization::new(&Default::default(), &Default::default(), nodes, 3, public().0).unwrap_err(), Error::InvalidThreshold);
+		assert_eq!(CompleteInitialization::new(&Default::default(), &Default::default(), BTreeMap::new(), 0, public().0).unwrap_err(), Error::InvalidNodesCount);
+	}
+
+	#[test]
+	fn debug_does_not_render_secrets() {
+		let secret1 = secret();
+		let secret2 = secret();
+		let keys_dissemination = KeysDissemination::new(&Default::default(), &Default::default(),
+			Random.generate().unwrap().public(), &secret1, &secret2, &[public().0]).unwrap();
+		let complaint_response = ComplaintResponse {
+			session: SerializableH256(Default::default()),
+			session_nonce: SerializableH256(Default::default()),
+			responding_to: public(),
+			secret1: secret1.clone(),
+			secret2: secret2.clone(),
+		};
+
+		let keys_dissemination = format!("{:?}", keys_dissemination);
+		let complaint_response = format!("{:?}", complaint_response);
+		assert!(complaint_response.contains("secret1: Secret(<redacted>), secret2: Secret(<redacted>)"));
+		for secret in &[secret1, secret2] {
+			assert!(!keys_dissemination.contains(&secret.to_hex()));
+			assert!(!complaint_response.contains(&secret.to_hex()));
+		}
+	}
+
+	#[test]
+	fn signed_message_sender_is_recovered() {
+		let key_pair = Random.generate().unwrap();
+		let message = Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(ConsensusVote {
+			session: SerializableH256(Default::default()),
+			approve: false,
+		}));
+
+		let mut signed = SignedMessage::sign(message, key_pair.secret()).unwrap();
+		assert_eq!(signed.recover_sender().unwrap(), *key_pair.public());
+
+		match signed.message {
+			Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(ref mut vote)) => vote.approve = true,
+			_ => unreachable!("message is not changed"),
+		}
+		assert!(signed.recover_sender().map(|sender| sender != *key_pair.public()).unwrap_or(true));
+	}
+
+	#[test]
+	fn broadcast_envelope_has_no_recipient() {
+		let from = Random.generate().unwrap().public().clone();
+		let to = Random.generate().unwrap().public().clone();
+		let message = Message::Cluster(ClusterMessage::KeepAlive(KeepAlive {}));
+
+		let envelope = Envelope::broadcast(from.clone(), message.clone());
+		assert!(envelope.to.is_none());
+		assert!(envelope.is_broadcast());
+		assert_eq!(*envelope.from, from);
+
+		let envelope = Envelope::direct(from, to.clone(), message);
+		assert!(!envelope.is_broadcast());
+		assert_eq!(envelope.to.map(|to| to.0), Some(to));
+	}
+
+	#[test]
+	fn future_version_is_incompatible() {
+		let message = VersionedMessage::new(Message::Cluster(ClusterMessage::KeepAlive(KeepAlive {})));
+		assert!(message.is_compatible());
+
+		let message = VersionedMessage {
+			version: CURRENT_VERSION + 1,
+			message: message.message,
+		};
+		assert!(!message.is_compatible());
+	}
+
+	fn cluster_messages() -> Vec<Message> {
+		vec![
+			Message::Cluster(ClusterMessage::NodePublicKey(NodePublicKey {
+				node_id: public(),
+				confirmation_plain: SerializableH256(Default::default()),
+			})),
+			Message::Cluster(ClusterMessage::NodePrivateKeySignature(NodePrivateKeySignature {
+				confirmation_signed: SerializableSignature(Default::default()),
+			})),
+			Message::Cluster(ClusterMessage::KeepAlive(KeepAlive {})),
+			Message::Cluster(ClusterMessage::KeepAliveResponse(KeepAliveResponse {})),
+		]
+	}
+
+	fn encryption_messages() -> Vec<Message> {
+		let session: MessageSessionId = SerializableH256(Default::default());
+		let nonce = SerializableH256(Default::default());
+		let nodes: BTreeMap<_, _> = (0..3).map(|_| (public(), secret())).collect();
+
+		vec![
+			Message::Encryption(EncryptionMessage::InitializeSession(InitializeSession {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				derived_point: public(),
+			})),
+			Message::Encryption(EncryptionMessage::ConfirmInitialization(ConfirmInitialization {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				derived_point: public(),
+			})),
+			Message::Encryption(EncryptionMessage::CompleteInitialization(CompleteInitialization {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				nodes: nodes,
+				threshold: 1,
+				derived_point: public(),
+			})),
+			Message::Encryption(EncryptionMessage::KeysDissemination(KeysDissemination::new(&session, &nonce,
+				Random.generate().unwrap().public(), &secret(), &secret(), &[public().0, public().0]).unwrap())),
+			Message::Encryption(EncryptionMessage::PublicKeyShare(PublicKeyShare {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				public_share: public(),
+			})),
+			Message::Encryption(EncryptionMessage::SessionError(SessionError::new(&session, &nonce, &Error::InvalidMessage))),
+			Message::Encryption(EncryptionMessage::SessionCompleted(SessionCompleted {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				common_point: public(),
+				encrypted_point: public(),
+			})),
+			Message::Encryption(EncryptionMessage::SessionKeepAlive(SessionKeepAlive {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				seq: 42,
+			})),
+			Message::Encryption(EncryptionMessage::Complaint(Complaint {
+				session: session.clone(),
+				session_nonce: nonce.clone(),
+				against: public(),
+			})),
+			Message::Encryption(EncryptionMessage::ComplaintResponse(ComplaintResponse {
+				session: session,
+				session_nonce: nonce.clone(),
+				responding_to: public(),
+				secret1: secret(),
+				secret2: secret(),
+			})),
+		]
+	}
+
+	fn decryption_messages() -> Vec<Message> {
+		let session: MessageSessionId = SerializableH256(Default::default());
+		let sub_session = secret();
+		let nodes: BTreeSet<_> = (0..3).map(|_| public()).collect();
+
+		vec![
+			Message::Decryption(DecryptionMessage::InitializeDecryptionSession(InitializeDecryptionSession {
+				session: session.clone(),
+				sub_session: sub_session.clone(),
+				requestor_signature: SerializableSignature(Default::default()),
+				is_shadow_decryption: true,
+			})),
+			Message::Decryption(DecryptionMessage::ConfirmDecryptionInitialization(ConfirmDecryptionInitialization {
+				session: session.clone(),
+				sub_session: sub_session.clone(),
+				is_confirmed: true,
+			})),
+			Message::Decryption(DecryptionMessage::RequestPartialDecryption(RequestPartialDecryption {
+				session: session.clone(),
+				sub_session: sub_session.clone(),
+				nodes: nodes,
+			})),
+			Message::Decryption(DecryptionMessage::PartialDecryption(PartialDecryption {
+				session: session.clone(),
+				sub_session: sub_session.clone(),
+				shadow_point: public(),
+				decrypt_shadow: Some(vec![1, 2, 3]),
+			})),
+			Message::Decryption(DecryptionMessage::DecryptionSessionError(DecryptionSessionError {
+				session: session.clone(),
+				sub_session: sub_session.clone(),
+				error: "error".into(),
+			})),
+			Message::Decryption(DecryptionMessage::DecryptionSessionCompleted(DecryptionSessionCompleted {
+				session: session,
+				sub_session: sub_session,
+			})),
+		]
+	}
+
+	fn share_add_messages() -> Vec<Message> {
+		let session: MessageSessionId = SerializableH256(Default::default());
+		let new_nodes: BTreeSet<_> = (0..2).map(|_| public()).collect();
+
+		vec![
+			Message::ShareAdd(ShareAddMessage::ShareAddConsensusInitiate(ShareAddConsensusInitiate {
+				session: session.clone(),
+				new_nodes: new_nodes,
+			})),
+			Message::ShareAdd(ShareAddMessage::NewKeysDissemination(NewKeysDissemination::new(&session,
+				Random.generate().unwrap().public(), &secret(), &[public().0, public().0]).unwrap())),
+			Message::ShareAdd(ShareAddMessage::ShareAddConfirm(ShareAddConfirm {
+				session: session,
+			})),
+		]
+	}
+
+	fn servers_set_change_messages() -> Vec<Message> {
+		let session: MessageSessionId = SerializableH256(Default::default());
+		let old_set: BTreeSet<_> = (0..3).map(|_| public()).collect();
+		let new_set: BTreeSet<_> = (0..4).map(|_| public()).collect();
+
+		vec![
+			Message::ServersSetChange(ServersSetChangeMessage::ConsensusInitiate(ConsensusInitiate {
+				session: session.clone(),
+				old_set: old_set,
+				new_set: new_set,
+			})),
+			Message::ServersSetChange(ServersSetChangeMessage::ConsensusVote(ConsensusVote {
+				session: session.clone(),
+				approve: true,
+			})),
+			Message::ServersSetChange(ServersSetChangeMessage::ChangeComplete(ChangeComplete {
+				session: session,
+			})),
+		]
+	}
+
+	fn batch_messages() -> Vec<Message> {
+		vec![
+			Message::Batch(MessageBatch::new(encryption_messages()).unwrap()),
+		]
+	}
+
+	fn retransmit_messages() -> Vec<Message> {
+		vec![
+			Message::Retransmit(Retransmit {
+				session: SerializableH256(Default::default()),
+				missing_type: 53,
+				from: public(),
+				sub_session: None,
+			}),
+		]
+	}
+
+	fn all_messages() -> Vec<Message> {
+		cluster_messages().into_iter()
+			.chain(encryption_messages())
+			.chain(decryption_messages())
+			.chain(share_add_messages())
+			.chain(servers_set_change_messages())
+			.chain(batch_messages())
+			.chain(retransmit_messages())
+			.collect()
+	}
+
+	#[test]
+	fn batch_with_messages_of_different_sessions_is_rejected() {
+		let mut messages = encryption_messages();
+		messages.push(Message::Encryption(EncryptionMessage::SessionKeepAlive(SessionKeepAlive {
+			session: SerializableH256(H256::from(1)),
+			session_nonce: SerializableH256(Default::default()),
+			seq: 1,
+		})));
+		assert_eq!(MessageBatch::new(messages).unwrap_err(), Error::InvalidSessionId);
+
+		assert_eq!(MessageBatch::new(vec![]).unwrap_err(), Error::InvalidMessage);
+		assert_eq!(MessageBatch::new(cluster_messages()).unwrap_err(), Error::InvalidMessage);
+		assert_eq!(MessageBatch::new(batch_messages()).unwrap_err(), Error::InvalidMessage);
+		assert_eq!(MessageBatch::new(retransmit_messages()).unwrap_err(), Error::InvalidMessage);
+
+		// same session id, but different session kinds
+		let session = SessionId::from(1);
+		let messages = vec![Message::share_add_confirm(&session), Message::change_complete(&session)];
+		assert_eq!(MessageBatch::new(messages).unwrap_err(), Error::InvalidSessionId);
+
+		// same session id, but different decryption sessions
+		let sub_session1 = math::generate_random_scalar().unwrap();
+		let sub_session2 = math::generate_random_scalar().unwrap();
+		let messages = vec![
+			Message::decryption_session_completed(&session, &sub_session1),
+			Message::decryption_session_completed(&session, &sub_session2),
+		];
+		assert_eq!(MessageBatch::new(messages).unwrap_err(), Error::InvalidSessionId);
+	}
+
+	#[test]
+	fn servers_set_change_requires_quorum_of_old_set() {
+		let old_set: Vec<_> = (0..4).map(|_| public()).collect();
+		let outsider = public();
+		let message = ConsensusInitiate {
+			session: SerializableH256(Default::default()),
+			old_set: old_set.iter().cloned().collect(),
+			new_set: (0..2).map(|_| public()).collect(),
+		};
+
+		let mut votes = BTreeMap::new();
+		votes.insert(old_set[0].0.clone(), true);
+		votes.insert(old_set[1].0.clone(), true);
+		votes.insert(old_set[2].0.clone(), false);
+		votes.insert(outsider.0.clone(), true);
+		assert!(!message.is_approved(&votes));
+
+		votes.insert(old_set[3].0.clone(), true);
+		assert!(message.is_approved(&votes));
+	}
+
+	#[test]
+	fn new_keys_dissemination_subshare_is_decrypted_by_recipient_only() {
+		let recipient = Random.generate().unwrap();
+		let secret_subshare = secret();
+		let message = NewKeysDissemination::new(&Default::default(), recipient.public(), &secret_subshare, &[public().0]).unwrap();
+
+		assert_eq!(message.decrypt_secret_subshare(recipient.secret()).unwrap(), secret_subshare.0);
+		assert!(message.decrypt_secret_subshare(Random.generate().unwrap().secret()).is_err());
+	}
+
+	#[test]
+	fn session_id_is_returned_for_session_messages_only() {
+		for message in cluster_messages() {
+			assert_eq!(message.session_id(), None);
+		}
+		for message in all_messages().into_iter().skip(cluster_messages().len()) {
+			assert_eq!(message.session_id(), Some(&SessionId::default()));
+		}
+	}
+
+	#[test]
+	fn messages_roundtrip() {
+		for message in all_messages() {
+			roundtrip(message);
+		}
+	}
+
+	#[test]
+	fn messages_encode_and_decode() {
+		for message in all_messages() {
+			let encoded = message.encode().unwrap();
+			let decoded = Message::decode(&encoded).unwrap();
+			assert_eq!(decoded.type_id(), message.type_id());
+			assert_eq!(serde_json::to_string(&decoded).unwrap(), serde_json::to_string(&message).unwrap());
+		}
+	}
+
+	#[test]
+	fn malformed_frames_are_not_decoded() {
+		let mut encoded = Message::Cluster(ClusterMessage::KeepAlive(KeepAlive {})).encode().unwrap();
+
+		// truncated header and payload
+		assert_eq!(Message::decode(&encoded[..MESSAGE_HEADER_SIZE - 1]).unwrap_err(), Error::InvalidMessage);
+		assert_eq!(Message::decode(&encoded[..encoded.len() - 1]).unwrap_err(), Error::InvalidMessage);
+
+		// trailing garbage
+		encoded.push(0);
+		assert_eq!(Message::decode(&encoded).unwrap_err(), Error::InvalidMessage);
+		encoded.pop();
+
+		// unknown type id
+		encoded[1] = 255;
+		assert!(Message::decode(&encoded).is_err());
+	}
+
+	#[test]
+	fn message_constructors_produce_expected_variants() {
+		let key_pair = Random.generate().unwrap();
+		let session = SessionId::from(1);
+		let nonce = H256::from(2);
+		let sub_session = math::generate_random_scalar().unwrap();
+		let signature = ethkey::sign(key_pair.secret(), &nonce).unwrap();
+		let secret = math::generate_random_scalar().unwrap();
+		let point = math::generate_random_point().unwrap();
+		let nodes: BTreeSet<_> = vec![key_pair.public().clone()].into_iter().collect();
+		let nodes_secrets: BTreeMap<_, _> = vec![(key_pair.public().clone(), secret.clone()), (point.clone(), math::generate_random_scalar().unwrap())].into_iter().collect();
+
+		let messages = vec![
+			Message::node_public_key(key_pair.public(), &nonce),
+			Message::node_private_key_signature(&signature),
+			Message::keep_alive(),
+			Message::keep_alive_response(),
+			Message::initialize_session(&session, &nonce, &point),
+			Message::confirm_initialization(&session, &nonce, &point),
+			Message::complete_initialization(&session, &nonce, nodes_secrets, 1, point.clone()).unwrap(),
+			Message::keys_dissemination(&session, &nonce, key_pair.public(), &secret, &secret, &[point.clone()]).unwrap(),
+			Message::public_key_share(&session, &nonce, &point),
+			Message::session_error(&session, &nonce, &Error::InvalidMessage),
+			Message::session_completed(&session, &nonce, &point, &point),
+			Message::session_keep_alive(&session, &nonce, 1),
+			Message::complaint(&session, &nonce, key_pair.public()),
+			Message::complaint_response(&session, &nonce, key_pair.public(), &secret, &secret),
+			Message::initialize_decryption_session(&session, &sub_session, &signature, false),
+			Message::confirm_decryption_initialization(&session, &sub_session, true),
+			Message::request_partial_decryption(&session, &sub_session, &nodes),
+			Message::partial_decryption(&session, &sub_session, point.clone(), None),
+			Message::decryption_session_error(&session, &sub_session, &Error::InvalidMessage),
+			Message::decryption_session_completed(&session, &sub_session),
+			Message::share_add_consensus_initiate(&session, &nodes),
+			Message::new_keys_dissemination(&session, key_pair.public(), &secret, &[point.clone()]).unwrap(),
+			Message::share_add_confirm(&session),
+			Message::consensus_initiate(&session, &nodes, &nodes),
+			Message::consensus_vote(&session, true),
+			Message::change_complete(&session),
+			Message::batch(vec![Message::share_add_confirm(&session), Message::share_add_confirm(&session)]).unwrap(),
+			Message::retransmit(&session, None, 53, key_pair.public()),
+		];
+
+		let ids: Vec<_> = messages.iter().map(|message| message.type_id()).collect();
+		let expected_ids: Vec<_> = all_messages().iter().map(|message| message.type_id()).collect();
+		assert_eq!(ids, expected_ids);
+		for message in messages.into_iter().skip(4) {
+			assert_eq!(message.session_id(), Some(&session));
+		}
+	}
+
+	#[test]
+	fn initialization_tally_is_complete_when_all_nodes_confirmed() {
+		let node1 = Random.generate().unwrap().public().clone();
+		let node2 = Random.generate().unwrap().public().clone();
+		let mut tally = InitializationTally::new(vec![node1.clone(), node2.clone()].into_iter().collect());
+		assert!(!tally.is_complete());
+
+		tally.record(node1.clone()).unwrap();
+		assert!(!tally.is_complete());
+
+		// duplicate confirmation is idempotent
+		tally.record(node1.clone()).unwrap();
+		assert_eq!(tally.confirmed.len(), 1);
+		assert!(!tally.is_complete());
+
+		// confirmation from unexpected node is rejected
+		assert_eq!(tally.record(Random.generate().unwrap().public().clone()), Err(Error::InvalidNodeId));
+		assert!(!tally.is_complete());
+
+		tally.record(node2).unwrap();
+		assert!(tally.is_complete());
+	}
+
+	#[test]
+	fn complaint_ledger_ejects_node_when_threshold_is_reached() {
+		let accused = Random.generate().unwrap().public().clone();
+		let other = Random.generate().unwrap().public().clone();
+		let complainant1 = Random.generate().unwrap().public().clone();
+		let complainant2 = Random.generate().unwrap().public().clone();
+		let mut ledger = ComplaintLedger::new();
+		assert!(!ledger.should_eject(&accused, 2));
+
+		assert!(ledger.record(accused.clone(), complainant1.clone()));
+		assert!(!ledger.should_eject(&accused, 2));
+
+		assert!(ledger.record(accused.clone(), complainant2.clone()));
+		assert!(ledger.should_eject(&accused, 2));
+		assert!(!ledger.should_eject(&accused, 3));
+		assert!(!ledger.should_eject(&other, 1));
+	}
+
+	#[test]
+	fn complaint_ledger_does_not_count_duplicate_complaints() {
+		let accused = Random.generate().unwrap().public().clone();
+		let complainant = Random.generate().unwrap().public().clone();
+		let mut ledger = ComplaintLedger::new();
+		assert!(ledger.record(accused.clone(), complainant.clone()));
+		assert!(!ledger.record(accused.clone(), complainant.clone()));
+		assert!(!ledger.record(accused.clone(), complainant));
+		assert_eq!(ledger.complainants_count(&accused), 1);
+		assert!(!ledger.should_eject(&accused, 2));
+	}
+
+	#[test]
+	fn complaint_ledger_never_ejects_with_zero_threshold() {
+		let accused = Random.generate().unwrap().public().clone();
+		let complainant = Random.generate().unwrap().public().clone();
+		let mut ledger = ComplaintLedger::new();
+		assert!(!ledger.should_eject(&accused, 0));
+
+		assert!(ledger.record(accused.clone(), complainant));
+		assert!(!ledger.should_eject(&accused, 0));
+		assert!(ledger.should_eject(&accused, 1));
+	}
+
+	#[test]
+	fn complete_initialization_with_duplicate_or_zero_ids_is_rejected() {
+		let node1 = Random.generate().unwrap().public().clone();
+		let node2 = Random.generate().unwrap().public().clone();
+		let id1 = math::generate_random_scalar().unwrap();
+		let id2 = math::generate_random_scalar().unwrap();
+		let zero = Secret::from_slice(&[0u8; 32]);
+		let nodes = |id1: &Secret, id2: &Secret| vec![(node1.clone(), id1.clone()), (node2.clone(), id2.clone())].into_iter().collect();
+
+		let message = CompleteInitialization::new(&Default::default(), &Default::default(), nodes(&id1, &id2), 1, public().0).unwrap();
+		assert_eq!(message.validate_ids(), Ok(()));
+
+		// duplicate id
+		let mut duplicate = message.clone();
+		duplicate.nodes = nodes(&id1, &id1).into_iter().map(|(id, number): (Public, Secret)| (id.into(), number.into())).collect();
+		assert_eq!(duplicate.validate_ids(), Err(Error::InvalidMessage));
+		assert_eq!(CompleteInitialization::new(&Default::default(), &Default::default(), nodes(&id1, &id1), 1, public().0).unwrap_err(),
+			Error::InvalidMessage);
+
+		// zero id
+		let mut with_zero = message.clone();
+		with_zero.nodes = nodes(&id1, &zero).into_iter().map(|(id, number): (Public, Secret)| (id.into(), number.into())).collect();
+		assert_eq!(with_zero.validate_ids(), Err(Error::InvalidMessage));
+		assert_eq!(CompleteInitialization::new(&Default::default(), &Default::default(), nodes(&zero, &id2), 1, public().0).unwrap_err(),
+			Error::InvalidMessage);
+	}
+
+	#[test]
+	fn message_type_ids_are_fixed_and_unique() {
+		let ids: Vec<_> = all_messages().into_iter().map(|message| message.type_id()).collect();
+		assert_eq!(ids, vec![1, 2, 3, 4, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 100, 101, 102, 103, 104, 105, 150, 151, 152, 200, 201, 202, 250, 251]);
+
+		let unique_ids: BTreeSet<_> = ids.iter().cloned().collect();
+		assert_eq!(unique_ids.len(), ids.len());
+	}
+
+	#[test]
+	fn retransmit_request_roundtrips() {
+		let session = SessionId::from(1);
+		let from = Random.generate().unwrap().public().clone();
+		let message = Message::retransmit(&session, None, 53, &from);
+
+		let decoded = Message::decode(&message.encode().unwrap()).unwrap();
+		match decoded {
+			Message::Retransmit(ref request) => {
+				assert_eq!(*request.session, session);
+				assert_eq!(request.missing_type, 53);
+				assert_eq!(*request.from, from);
+			},
+			_ => panic!("expected Retransmit, got {}", decoded),
+		}
+		roundtrip(message);
+	}
+
+	#[test]
+	fn sent_message_is_found_by_retransmit_request() {
+		let self_node = Random.generate().unwrap().public().clone();
+		let requestor = Random.generate().unwrap().public().clone();
+		let session = SessionId::from(1);
+		let message = Message::share_add_confirm(&session);
+		let mut sent = SentMessages::new();
+		sent.record(&requestor, &message);
+		sent.record(&requestor, &Message::keep_alive());
+		assert_eq!(sent.messages.len(), 1);
+
+		let request = Retransmit {
+			session: session.clone().into(),
+			missing_type: message.type_id(),
+			from: self_node.clone().into(),
+			sub_session: None,
+		};
+		assert_eq!(sent.lookup(&self_node, &requestor, &request).map(|m| m.type_id()), Some(152));
+		// message has been sent to the other node
+		assert!(sent.lookup(&self_node, &self_node, &request).is_none());
+		// request is addressed to the other node
+		assert!(sent.lookup(&requestor, &requestor, &request).is_none());
+
+		// messages of the other session kind are kept
+		sent.remove_session(&ScopedSessionId::new(SessionKind::Encryption, session.clone()));
+		assert!(sent.lookup(&self_node, &requestor, &request).is_some());
+
+		sent.remove_session(&ScopedSessionId::new(SessionKind::ShareAdd, session));
+		assert!(sent.lookup(&self_node, &requestor, &request).is_none());
+	}
+
+	#[test]
+	fn scoped_session_ids_differing_in_kind_are_not_equal() {
+		let session = SessionId::from(1);
+		let encryption = ScopedSessionId::new(SessionKind::Encryption, session.clone());
+		let sub_session = math::generate_random_scalar().unwrap();
+		let decryption = ScopedSessionId::decryption(session.clone(), sub_session.clone());
+		assert_eq!(encryption.id, decryption.id);
+		assert!(encryption != decryption);
+		assert_eq!(encryption, ScopedSessionId::new(SessionKind::Encryption, session.clone()));
+
+		let key_pair = Random.generate().unwrap();
+		assert_eq!(Message::session_keep_alive(&session, &Default::default(), 1).scoped_session_id(), Some(encryption));
+		assert_eq!(Message::decryption_session_completed(&session, &sub_session).scoped_session_id(), Some(decryption.clone()));
+		assert_eq!(Message::retransmit(&session, Some(&sub_session), 105, key_pair.public()).scoped_session_id(), Some(decryption));
+		assert_eq!(Message::keep_alive().scoped_session_id(), None);
+	}
+
+	#[test]
+	fn concurrent_decryption_sessions_are_retransmitted_separately() {
+		let self_node = Random.generate().unwrap().public().clone();
+		let requestor = Random.generate().unwrap().public().clone();
+		let session = SessionId::from(1);
+		let sub_session1 = math::generate_random_scalar().unwrap();
+		let sub_session2 = math::generate_random_scalar().unwrap();
+		let mut sent = SentMessages::new();
+		sent.record(&requestor, &Message::decryption_session_completed(&session, &sub_session1));
+		sent.record(&requestor, &Message::decryption_session_completed(&session, &sub_session2));
+		assert_eq!(sent.messages.len(), 2);
+
+		let request = |sub_session: Option<&Secret>| match Message::retransmit(&session, sub_session, 105, &self_node) {
+			Message::Retransmit(request) => request,
+			_ => unreachable!("retransmit creates Retransmit message; qed"),
+		};
+		let sub_session_of = |message: Option<&Message>| match message {
+			Some(&Message::Decryption(ref message)) => Some(message.sub_session_id().clone()),
+			_ => None,
+		};
+		assert_eq!(sub_session_of(sent.lookup(&self_node, &requestor, &request(Some(&sub_session1)))), Some(sub_session1.clone()));
+		assert_eq!(sub_session_of(sent.lookup(&self_node, &requestor, &request(Some(&sub_session2)))), Some(sub_session2.clone()));
+		// decryption session can't be identified without sub-session
+		assert!(sent.lookup(&self_node, &requestor, &request(None)).is_none());
+
+		sent.remove_session(&ScopedSessionId::decryption(session.clone(), sub_session1.clone()));
+		assert!(sent.lookup(&self_node, &requestor, &request(Some(&sub_session1))).is_none());
+		assert!(sent.lookup(&self_node, &requestor, &request(Some(&sub_session2))).is_some());
+	}
 }
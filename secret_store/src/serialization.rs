@@ -19,8 +19,10 @@ use std::cmp::{Ord, PartialOrd, Ordering};
 use std::ops::Deref;
 use rustc_serialize::hex::{ToHex, FromHex};
 use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::ser::Error as SerdeSerError;
 use serde::de::{Visitor, Error as SerdeError};
 use ethkey::{Public, Secret, Signature};
+use ethkey::math::{public_to_compressed, public_from_compressed};
 use util::{H256, Bytes};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -200,10 +202,17 @@ impl Deserialize for SerializableH256 {
 	}
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 /// Serializable EC scalar/secret key.
 pub struct SerializableSecret(pub Secret);
 
+impl fmt::Debug for SerializableSecret {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// secret values must never reach logs
+		write!(f, "Secret(<redacted>)")
+	}
+}
+
 impl<T> From<T> for SerializableSecret where Secret: From<T> {
 	fn from(s: T) -> SerializableSecret {
 		SerializableSecret(s.into())
@@ -340,10 +349,75 @@ impl Deserialize for SerializablePublic {
 	}
 }
 
+#[derive(Clone, Debug, PartialEq)]
+/// Serializable EC point/public key, serialized in compressed form.
+pub struct SerializableCompressedPublic(pub Public);
+
+impl<T> From<T> for SerializableCompressedPublic where Public: From<T> {
+	fn from(p: T) -> SerializableCompressedPublic {
+		SerializableCompressedPublic(p.into())
+	}
+}
+
+impl Into<Public> for SerializableCompressedPublic {
+	fn into(self) -> Public {
+		self.0
+	}
+}
+
+impl Deref for SerializableCompressedPublic {
+	type Target = Public;
+
+	fn deref(&self) -> &Public {
+		&self.0
+	}
+}
+
+impl Serialize for SerializableCompressedPublic {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+		let compressed = public_to_compressed(&self.0).map_err(SerdeSerError::custom)?;
+		let mut serialized = "0x".to_owned();
+		serialized.push_str(compressed.to_hex().as_ref());
+		serializer.serialize_str(serialized.as_ref())
+	}
+}
+
+impl Deserialize for SerializableCompressedPublic {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer {
+		struct HashVisitor;
+
+		impl Visitor for HashVisitor {
+			type Value = SerializableCompressedPublic;
+
+			fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+				write!(formatter, "a hex-encoded compressed EC point")
+			}
+
+			fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> where E: SerdeError {
+				if value.len() >= 2 && &value[0..2] == "0x" && value.len() & 1 == 0 {
+					value[2..].from_hex()
+						.map_err(SerdeError::custom)
+						.and_then(|compressed| public_from_compressed(&compressed).map_err(SerdeError::custom))
+						.map(SerializableCompressedPublic)
+				} else {
+					Err(SerdeError::custom("invalid format"))
+				}
+			}
+
+			fn visit_string<E>(self, value: String) -> Result<Self::Value, E> where E: SerdeError {
+				self.visit_str(value.as_ref())
+			}
+		}
+
+		deserializer.deserialize(HashVisitor)
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use serde_json;
-	use super::{SerializableBytes, SerializablePublic};
+	use ethkey::{Random, Generator};
+	use super::{SerializableBytes, SerializablePublic, SerializableCompressedPublic};
 
 	#[test]
 	fn serialize_and_deserialize_bytes() {
@@ -362,4 +436,13 @@ mod tests {
 		let public_deserialized: SerializablePublic = serde_json::from_str(&public_serialized).unwrap();
 		assert_eq!(public_deserialized, public);
 	}
+
+	#[test]
+	fn serialize_and_deserialize_compressed_public() {
+		let public = SerializableCompressedPublic(Random.generate().unwrap().public().clone());
+		let public_serialized = serde_json::to_string(&public).unwrap();
+		assert_eq!(public_serialized.len(), 2 + 2 + 33 * 2);
+		let public_deserialized: SerializableCompressedPublic = serde_json::from_str(&public_serialized).unwrap();
+		assert_eq!(public_deserialized, public);
+	}
 }